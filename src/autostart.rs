@@ -0,0 +1,62 @@
+// 开机启动项，写入 HKCU 的 Run 注册表项
+use crate::registry::{RegValue, Registry};
+use std::error::Error;
+use std::io;
+
+pub const RUN_KEY: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\Run";
+
+pub fn set_autostart(
+    registry: &dyn Registry,
+    app_name: &str,
+    app_path: &str,
+) -> Result<(), Box<dyn Error>> {
+    // 创建或打开注册表项
+    registry.create_key(RUN_KEY)?;
+
+    // 设置注册表项值
+    registry.set_value(RUN_KEY, app_name, &RegValue::String(app_path.to_owned()))?;
+    Ok(())
+}
+
+pub fn remove_startup_entry(registry: &dyn Registry, app_name: &str) -> Result<(), Box<dyn Error>> {
+    // 删除注册表项值，不存在时忽略
+    match registry.delete_value(RUN_KEY, app_name) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::MemoryRegistry;
+
+    #[test]
+    fn set_autostart_creates_run_entry() {
+        let registry = MemoryRegistry::default();
+        set_autostart(&registry, "autodark", r"C:\autodark.exe").unwrap();
+        assert_eq!(
+            registry
+                .get_value(r"Software\Microsoft\Windows\CurrentVersion\Run", "autodark")
+                .unwrap(),
+            RegValue::String(r"C:\autodark.exe".to_owned())
+        );
+    }
+
+    #[test]
+    fn remove_startup_entry_ignores_missing_values() {
+        let registry = MemoryRegistry::default();
+        remove_startup_entry(&registry, "autodark").unwrap();
+        set_autostart(&registry, "autodark", r"C:\autodark.exe").unwrap();
+        remove_startup_entry(&registry, "autodark").unwrap();
+        assert!(registry.get_value(RUN_KEY, "autodark").is_err());
+    }
+
+    #[test]
+    fn access_errors_are_reported() {
+        let registry = MemoryRegistry::default();
+        registry.deny_access(RUN_KEY);
+        assert!(set_autostart(&registry, "autodark", r"C:\autodark.exe").is_err());
+        assert!(remove_startup_entry(&registry, "autodark").is_err());
+    }
+}
@@ -0,0 +1,177 @@
+// GNOME 后端：通过 gsettings 读写 org.gnome.desktop.interface
+// color-scheme 只有一个，所以默认应用模式和默认windows模式是同一个设置
+use super::{Capabilities, ThemeBackend};
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::Command;
+
+const SCHEMA: &str = "org.gnome.desktop.interface";
+
+#[derive(Debug)]
+pub struct GnomeBackend {
+    program: PathBuf,
+    // 为空时不修改 gtk-theme
+    gtk_light_theme: String,
+    gtk_dark_theme: String,
+}
+
+impl GnomeBackend {
+    pub fn new(program: impl Into<PathBuf>, gtk_light_theme: &str, gtk_dark_theme: &str) -> Self {
+        Self {
+            program: program.into(),
+            gtk_light_theme: gtk_light_theme.to_owned(),
+            gtk_dark_theme: gtk_dark_theme.to_owned(),
+        }
+    }
+
+    fn get(&self, key: &str) -> Result<String, Box<dyn Error>> {
+        let output = Command::new(&self.program)
+            .args(["get", SCHEMA, key])
+            .output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "gsettings get {} failed: {}",
+                key,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        // gsettings 输出的是 GVariant 文本，例如 'prefer-dark'
+        let value = String::from_utf8(output.stdout)?;
+        Ok(value.trim().trim_matches('\'').to_owned())
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<(), Box<dyn Error>> {
+        let output = Command::new(&self.program)
+            .args(["set", SCHEMA, key, value])
+            .output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "gsettings set {} failed: {}",
+                key,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl ThemeBackend for GnomeBackend {
+    fn name(&self) -> &'static str {
+        "gnome"
+    }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            write: true,
+            separate_system_mode: false,
+        }
+    }
+    fn is_dark_mode_enabled(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(self.get("color-scheme")? == "prefer-dark")
+    }
+    fn set_dark_mode(&self, enabled: bool) -> Result<(), Box<dyn Error>> {
+        self.set(
+            "color-scheme",
+            if enabled { "prefer-dark" } else { "default" },
+        )?;
+        let gtk_theme = if enabled {
+            &self.gtk_dark_theme
+        } else {
+            &self.gtk_light_theme
+        };
+        if !gtk_theme.is_empty() {
+            self.set("gtk-theme", gtk_theme)?;
+        }
+        Ok(())
+    }
+    fn is_system_dark_mode_enabled(&self) -> Result<bool, Box<dyn Error>> {
+        self.is_dark_mode_enabled()
+    }
+    fn set_system_dark_mode(&self, enabled: bool) -> Result<(), Box<dyn Error>> {
+        self.set_dark_mode(enabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    // 假的 gsettings：每个键存成一个文件，并记录每次调用
+    const FAKE_GSETTINGS: &str = r#"#!/bin/sh
+dir=$(dirname "$0")
+echo "$@" >> "$dir/calls"
+case "$1" in
+get)
+    [ -f "$dir/$3" ] || { echo "No such key '$3'" >&2; exit 1; }
+    cat "$dir/$3"
+    ;;
+set)
+    printf "'%s'\n" "$4" > "$dir/$3"
+    ;;
+esac
+"#;
+
+    fn fake_gsettings(dir: &Path) -> PathBuf {
+        let program = dir.join("gsettings");
+        fs::write(&program, FAKE_GSETTINGS).unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(dir.join("color-scheme"), "'default'\n").unwrap();
+        program
+    }
+
+    #[test]
+    fn reads_color_scheme() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = GnomeBackend::new(fake_gsettings(dir.path()), "", "");
+        assert!(!backend.is_dark_mode_enabled().unwrap());
+        fs::write(dir.path().join("color-scheme"), "'prefer-dark'\n").unwrap();
+        assert!(backend.is_dark_mode_enabled().unwrap());
+        assert!(backend.is_system_dark_mode_enabled().unwrap());
+    }
+
+    #[test]
+    fn writes_color_scheme_without_touching_gtk_theme() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = GnomeBackend::new(fake_gsettings(dir.path()), "", "");
+        backend.set_dark_mode(true).unwrap();
+        backend.set_dark_mode(false).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("calls")).unwrap(),
+            "set org.gnome.desktop.interface color-scheme prefer-dark\n\
+             set org.gnome.desktop.interface color-scheme default\n"
+        );
+        assert!(!dir.path().join("gtk-theme").exists());
+    }
+
+    #[test]
+    fn switches_gtk_theme_when_configured() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = GnomeBackend::new(fake_gsettings(dir.path()), "Adwaita", "Adwaita-dark");
+        backend.set_dark_mode(true).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("gtk-theme")).unwrap(),
+            "'Adwaita-dark'\n"
+        );
+        backend.set_system_dark_mode(false).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("gtk-theme")).unwrap(),
+            "'Adwaita'\n"
+        );
+    }
+
+    #[test]
+    fn reports_gsettings_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = GnomeBackend::new(fake_gsettings(dir.path()), "", "");
+        fs::remove_file(dir.path().join("color-scheme")).unwrap();
+        let err = backend.is_dark_mode_enabled().unwrap_err();
+        assert!(err.to_string().contains("No such key"));
+
+        let missing = GnomeBackend::new(dir.path().join("missing"), "", "");
+        assert!(missing.set_dark_mode(true).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+// KDE Plasma 后端：在配置的浅色/深色全局主题(或配色方案)之间切换，
+// 当前主题从 kdeglobals 读取。Plasma 只有一个配色，没有单独的默认windows模式
+use super::{Capabilities, KdeThemeKind, ThemeBackend};
+use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+#[derive(Debug)]
+pub struct KdeBackend {
+    // kdeglobals 所在目录，通常是 ~/.config
+    config_dir: PathBuf,
+    kind: KdeThemeKind,
+    light_theme: String,
+    dark_theme: String,
+    // 应用主题的命令，默认根据 kind 选择 plasma-apply-lookandfeel 或 plasma-apply-colorscheme
+    program: PathBuf,
+}
+
+impl KdeBackend {
+    pub fn new(
+        config_dir: impl Into<PathBuf>,
+        kind: KdeThemeKind,
+        light_theme: &str,
+        dark_theme: &str,
+    ) -> Self {
+        let program = match kind {
+            KdeThemeKind::LookAndFeel => "plasma-apply-lookandfeel",
+            KdeThemeKind::ColorScheme => "plasma-apply-colorscheme",
+        };
+        Self {
+            config_dir: config_dir.into(),
+            kind,
+            light_theme: light_theme.to_owned(),
+            dark_theme: dark_theme.to_owned(),
+            program: program.into(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_program(mut self, program: impl Into<PathBuf>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    // 读取 kdeglobals 中当前的全局主题或配色方案名称
+    pub fn current_theme(&self) -> Result<String, Box<dyn Error>> {
+        let path = self.config_dir.join("kdeglobals");
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let (group, key) = match self.kind {
+            KdeThemeKind::LookAndFeel => ("KDE", "LookAndFeelPackage"),
+            KdeThemeKind::ColorScheme => ("General", "ColorScheme"),
+        };
+        read_ini_value(&text, group, key)
+            .ok_or_else(|| format!("{} has no [{}] {}", path.display(), group, key).into())
+    }
+
+    fn apply(&self, theme: &str) -> Result<(), Box<dyn Error>> {
+        let mut command = Command::new(&self.program);
+        if self.kind == KdeThemeKind::LookAndFeel {
+            command.arg("-a");
+        }
+        let output = command.arg(theme).output()?;
+        if !output.status.success() {
+            return Err(format!(
+                "{} {} failed: {}",
+                self.program.display(),
+                theme,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+// kdeglobals 是 INI 格式，只取 [group] 下第一个 key=value
+fn read_ini_value(text: &str, group: &str, key: &str) -> Option<String> {
+    let mut in_group = false;
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_group = line == format!("[{}]", group);
+        } else if in_group {
+            if let Some((name, value)) = line.split_once('=') {
+                if name.trim() == key {
+                    return Some(value.trim().to_owned());
+                }
+            }
+        }
+    }
+    None
+}
+
+impl ThemeBackend for KdeBackend {
+    fn name(&self) -> &'static str {
+        "kde"
+    }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            write: true,
+            separate_system_mode: false,
+        }
+    }
+    fn is_dark_mode_enabled(&self) -> Result<bool, Box<dyn Error>> {
+        let current = self.current_theme()?;
+        if current == self.dark_theme {
+            Ok(true)
+        } else if current == self.light_theme {
+            Ok(false)
+        } else {
+            // 不是配置里的主题时按名称猜测，例如 org.kde.breezedark.desktop
+            Ok(current.to_lowercase().contains("dark"))
+        }
+    }
+    fn set_dark_mode(&self, enabled: bool) -> Result<(), Box<dyn Error>> {
+        self.apply(if enabled {
+            &self.dark_theme
+        } else {
+            &self.light_theme
+        })
+    }
+    fn is_system_dark_mode_enabled(&self) -> Result<bool, Box<dyn Error>> {
+        self.is_dark_mode_enabled()
+    }
+    fn set_system_dark_mode(&self, enabled: bool) -> Result<(), Box<dyn Error>> {
+        self.set_dark_mode(enabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    // 假的 plasma-apply-*：把主题写回同目录的 kdeglobals
+    const FAKE_LOOKANDFEEL: &str = r#"#!/bin/sh
+[ "$1" = "-a" ] || exit 2
+printf '[General]\nColorScheme=Whatever\n\n[KDE]\nLookAndFeelPackage=%s\n' "$2" > "$(dirname "$0")/kdeglobals"
+"#;
+    const FAKE_COLORSCHEME: &str = r#"#!/bin/sh
+printf '[General]\nColorScheme=%s\n' "$1" > "$(dirname "$0")/kdeglobals"
+"#;
+
+    fn install(dir: &Path, script: &str) -> PathBuf {
+        let program = dir.join("plasma-apply");
+        fs::write(&program, script).unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+        program
+    }
+
+    fn look_and_feel(dir: &Path) -> KdeBackend {
+        KdeBackend::new(
+            dir,
+            KdeThemeKind::LookAndFeel,
+            "org.kde.breeze.desktop",
+            "org.kde.breezedark.desktop",
+        )
+        .with_program(install(dir, FAKE_LOOKANDFEEL))
+    }
+
+    #[test]
+    fn reads_look_and_feel_from_kdeglobals() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = look_and_feel(dir.path());
+        fs::write(
+            dir.path().join("kdeglobals"),
+            "[General]\nColorScheme=BreezeDark\n\n[KDE]\nLookAndFeelPackage=org.kde.breeze.desktop\n",
+        )
+        .unwrap();
+        assert!(!backend.is_dark_mode_enabled().unwrap());
+        assert!(!backend.is_system_dark_mode_enabled().unwrap());
+    }
+
+    #[test]
+    fn switches_look_and_feel_package() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = look_and_feel(dir.path());
+        backend.set_dark_mode(true).unwrap();
+        assert_eq!(
+            backend.current_theme().unwrap(),
+            "org.kde.breezedark.desktop"
+        );
+        assert!(backend.is_dark_mode_enabled().unwrap());
+        backend.set_system_dark_mode(false).unwrap();
+        assert!(!backend.is_system_dark_mode_enabled().unwrap());
+    }
+
+    #[test]
+    fn switches_color_scheme() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = KdeBackend::new(
+            dir.path(),
+            KdeThemeKind::ColorScheme,
+            "BreezeLight",
+            "BreezeDark",
+        )
+        .with_program(install(dir.path(), FAKE_COLORSCHEME));
+        backend.set_dark_mode(true).unwrap();
+        assert_eq!(backend.current_theme().unwrap(), "BreezeDark");
+        assert!(backend.is_dark_mode_enabled().unwrap());
+    }
+
+    #[test]
+    fn unknown_themes_are_guessed_by_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = look_and_feel(dir.path());
+        fs::write(
+            dir.path().join("kdeglobals"),
+            "[KDE]\nLookAndFeelPackage=com.example.nord-dark\n",
+        )
+        .unwrap();
+        assert!(backend.is_dark_mode_enabled().unwrap());
+    }
+
+    #[test]
+    fn reports_missing_config_and_failed_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let backend = look_and_feel(dir.path());
+        assert!(backend.is_dark_mode_enabled().is_err());
+        fs::write(dir.path().join("kdeglobals"), "[General]\nName=Breeze\n").unwrap();
+        assert!(backend.current_theme().is_err());
+
+        let backend = backend.with_program(dir.path().join("missing"));
+        assert!(backend.set_dark_mode(true).is_err());
+    }
+}
@@ -0,0 +1,41 @@
+// 内存后端：不修改系统设置，用于非 Windows 平台和测试
+use super::{Capabilities, ThemeBackend};
+use std::error::Error;
+use std::sync::Mutex;
+
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    is_dark_mode: bool,
+    is_system_dark_mode: bool,
+}
+
+impl ThemeBackend for MemoryBackend {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            write: true,
+            separate_system_mode: true,
+        }
+    }
+    fn is_dark_mode_enabled(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(self.state.lock().unwrap().is_dark_mode)
+    }
+    fn set_dark_mode(&self, enabled: bool) -> Result<(), Box<dyn Error>> {
+        self.state.lock().unwrap().is_dark_mode = enabled;
+        Ok(())
+    }
+    fn is_system_dark_mode_enabled(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(self.state.lock().unwrap().is_system_dark_mode)
+    }
+    fn set_system_dark_mode(&self, enabled: bool) -> Result<(), Box<dyn Error>> {
+        self.state.lock().unwrap().is_system_dark_mode = enabled;
+        Ok(())
+    }
+}
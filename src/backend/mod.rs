@@ -0,0 +1,163 @@
+// 主题后端：把“读取/设置深浅模式”从具体平台实现中抽离出来，
+// MyApp 只通过 ThemeBackend 与系统交互
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(windows))]
+mod gnome;
+#[cfg(not(windows))]
+mod kde;
+mod memory;
+#[cfg(target_os = "linux")]
+mod portal;
+#[cfg_attr(not(windows), allow(dead_code))]
+mod registry;
+
+#[cfg(not(windows))]
+pub use gnome::GnomeBackend;
+#[cfg(not(windows))]
+pub use kde::KdeBackend;
+pub use memory::MemoryBackend;
+#[cfg(target_os = "linux")]
+pub use portal::PortalBackend;
+#[cfg(windows)]
+pub use registry::RegistryBackend;
+
+// 后端能力说明，界面根据它决定显示哪些控件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    // 是否可以修改主题
+    pub write: bool,
+    // 默认windows模式(任务栏)是否可以与默认应用模式分开设置
+    pub separate_system_mode: bool,
+}
+
+pub trait ThemeBackend: fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+    fn capabilities(&self) -> Capabilities;
+    // 默认应用模式(常规应用)
+    fn is_dark_mode_enabled(&self) -> Result<bool, Box<dyn Error>>;
+    fn set_dark_mode(&self, enabled: bool) -> Result<(), Box<dyn Error>>;
+    // 默认windows模式(任务栏)
+    fn is_system_dark_mode_enabled(&self) -> Result<bool, Box<dyn Error>>;
+    fn set_system_dark_mode(&self, enabled: bool) -> Result<(), Box<dyn Error>>;
+    // 主题被外部修改时调用 on_change，不支持通知的后端什么也不做
+    fn watch(&self, _on_change: Box<dyn Fn() + Send>) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+// 各个后端自己的设置，保存在配置文件的 [backend] 中
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackendSettings {
+    // GNOME 下切换时同时设置的 gtk-theme，为空则不修改
+    pub gtk_light_theme: String,
+    pub gtk_dark_theme: String,
+    // KDE 下切换的是全局主题(look-and-feel)还是配色方案
+    pub kde_theme_kind: KdeThemeKind,
+    pub kde_light_theme: String,
+    pub kde_dark_theme: String,
+}
+
+impl Default for BackendSettings {
+    fn default() -> Self {
+        Self {
+            gtk_light_theme: String::new(),
+            gtk_dark_theme: String::new(),
+            kde_theme_kind: KdeThemeKind::LookAndFeel,
+            kde_light_theme: "org.kde.breeze.desktop".to_owned(),
+            kde_dark_theme: "org.kde.breezedark.desktop".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KdeThemeKind {
+    LookAndFeel,
+    ColorScheme,
+}
+
+// 启动时选择后端，可以用环境变量 AUTODARK_BACKEND 指定
+pub fn select_backend(settings: &BackendSettings) -> Arc<dyn ThemeBackend> {
+    match env::var("AUTODARK_BACKEND").as_deref() {
+        Ok("memory") => Arc::new(MemoryBackend::default()),
+        #[cfg(not(windows))]
+        Ok("gnome") => gnome_backend(settings),
+        #[cfg(not(windows))]
+        Ok("kde") => kde_backend(settings),
+        #[cfg(target_os = "linux")]
+        Ok("portal") => match PortalBackend::connect(desktop_backend(settings)) {
+            Ok(portal) => Arc::new(portal),
+            Err(e) => {
+                eprintln!("Desktop portal is not available: {}", e);
+                default_backend(settings)
+            }
+        },
+        Ok(name) => {
+            eprintln!("Unknown backend {}, using the default one", name);
+            default_backend(settings)
+        }
+        Err(_) => default_backend(settings),
+    }
+}
+
+#[cfg(windows)]
+fn default_backend(_settings: &BackendSettings) -> Arc<dyn ThemeBackend> {
+    Arc::new(RegistryBackend::new(Arc::new(crate::registry::WinRegistry)))
+}
+
+#[cfg(not(windows))]
+fn default_backend(settings: &BackendSettings) -> Arc<dyn ThemeBackend> {
+    let desktop = desktop_backend(settings);
+    // Linux 下优先从桌面门户读取，修改仍然交给桌面自己的后端
+    #[cfg(target_os = "linux")]
+    if let Ok(portal) = PortalBackend::connect(desktop.clone()) {
+        return Arc::new(portal);
+    }
+    desktop.unwrap_or_else(|| Arc::new(MemoryBackend::default()))
+}
+
+// 根据当前桌面选择可以修改主题的后端
+#[cfg(not(windows))]
+fn desktop_backend(settings: &BackendSettings) -> Option<Arc<dyn ThemeBackend>> {
+    // XDG_CURRENT_DESKTOP 可能是 "ubuntu:GNOME" 这样的列表
+    let desktop = env::var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+    for name in desktop.split(':') {
+        if name.eq_ignore_ascii_case("gnome") {
+            return Some(gnome_backend(settings));
+        }
+        if name.eq_ignore_ascii_case("kde") {
+            return Some(kde_backend(settings));
+        }
+    }
+    None
+}
+
+#[cfg(not(windows))]
+fn gnome_backend(settings: &BackendSettings) -> Arc<dyn ThemeBackend> {
+    Arc::new(GnomeBackend::new(
+        "gsettings",
+        &settings.gtk_light_theme,
+        &settings.gtk_dark_theme,
+    ))
+}
+
+#[cfg(not(windows))]
+fn kde_backend(settings: &BackendSettings) -> Arc<dyn ThemeBackend> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".config")))
+        .unwrap_or_default();
+    Arc::new(KdeBackend::new(
+        config_dir,
+        settings.kde_theme_kind,
+        &settings.kde_light_theme,
+        &settings.kde_dark_theme,
+    ))
+}
@@ -0,0 +1,327 @@
+// XDG 桌面门户后端：从 org.freedesktop.portal.Settings 读取
+// org.freedesktop.appearance color-scheme，并监听 SettingChanged 信号。
+// 门户是只读的，修改主题时交给具体桌面的后端(GNOME/KDE)
+use super::{Capabilities, ThemeBackend};
+use std::error::Error;
+use std::sync::Arc;
+use std::thread;
+use zbus::blocking::{Connection, Proxy};
+use zbus::zvariant::{OwnedValue, Value};
+
+const DESTINATION: &str = "org.freedesktop.portal.Desktop";
+const PATH: &str = "/org/freedesktop/portal/desktop";
+const INTERFACE: &str = "org.freedesktop.portal.Settings";
+const NAMESPACE: &str = "org.freedesktop.appearance";
+const KEY: &str = "color-scheme";
+
+// color-scheme 的取值：0 没有偏好，1 偏好深色，2 偏好浅色
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorScheme {
+    NoPreference,
+    PreferDark,
+    PreferLight,
+}
+
+impl ColorScheme {
+    fn from_value(value: &Value<'_>) -> Result<Self, Box<dyn Error>> {
+        match value {
+            // 旧版门户的 Read 会再包一层 variant
+            Value::Value(inner) => Self::from_value(inner),
+            Value::U32(0) => Ok(ColorScheme::NoPreference),
+            Value::U32(1) => Ok(ColorScheme::PreferDark),
+            Value::U32(2) => Ok(ColorScheme::PreferLight),
+            other => Err(format!("unexpected color-scheme value {:?}", other).into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PortalBackend {
+    connection: Connection,
+    writer: Option<Arc<dyn ThemeBackend>>,
+}
+
+impl PortalBackend {
+    pub fn new(connection: Connection, writer: Option<Arc<dyn ThemeBackend>>) -> Self {
+        Self { connection, writer }
+    }
+
+    // 连接会话总线，门户不可用时返回错误
+    pub fn connect(writer: Option<Arc<dyn ThemeBackend>>) -> Result<Self, Box<dyn Error>> {
+        let backend = Self::new(Connection::session()?, writer);
+        backend.color_scheme()?;
+        Ok(backend)
+    }
+
+    fn proxy(connection: &Connection) -> zbus::Result<Proxy<'static>> {
+        Proxy::new(connection, DESTINATION, PATH, INTERFACE)
+    }
+
+    pub fn color_scheme(&self) -> Result<ColorScheme, Box<dyn Error>> {
+        let proxy = Self::proxy(&self.connection)?;
+        // ReadOne 是第 2 版接口新增的，旧版只有 Read
+        let value: OwnedValue = match proxy.call("ReadOne", &(NAMESPACE, KEY)) {
+            Ok(value) => value,
+            Err(_) => proxy.call("Read", &(NAMESPACE, KEY))?,
+        };
+        ColorScheme::from_value(&value)
+    }
+
+    fn writer(&self) -> Result<&dyn ThemeBackend, Box<dyn Error>> {
+        self.writer
+            .as_deref()
+            .ok_or_else(|| "the desktop portal is read-only".into())
+    }
+}
+
+// 在后台线程中监听 color-scheme 的变化，每次变化调用 on_change
+pub fn watch_color_scheme(
+    connection: &Connection,
+    on_change: impl Fn(ColorScheme) + Send + 'static,
+) -> Result<thread::JoinHandle<()>, Box<dyn Error>> {
+    let proxy = PortalBackend::proxy(connection)?;
+    // 先订阅再启动线程，这样返回之后发出的信号不会丢失
+    let signals = proxy.receive_signal_with_args("SettingChanged", &[(0, NAMESPACE), (1, KEY)])?;
+    Ok(thread::spawn(move || {
+        for message in signals {
+            match message.body::<(String, String, OwnedValue)>() {
+                Ok((_, _, value)) => match ColorScheme::from_value(&value) {
+                    Ok(scheme) => on_change(scheme),
+                    Err(e) => eprintln!("Ignoring SettingChanged: {}", e),
+                },
+                Err(e) => eprintln!("Ignoring SettingChanged: {}", e),
+            }
+        }
+    }))
+}
+
+impl ThemeBackend for PortalBackend {
+    fn name(&self) -> &'static str {
+        "xdg-portal"
+    }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            write: self.writer.as_ref().is_some_and(|w| w.capabilities().write),
+            separate_system_mode: false,
+        }
+    }
+    fn is_dark_mode_enabled(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(self.color_scheme()? == ColorScheme::PreferDark)
+    }
+    fn set_dark_mode(&self, enabled: bool) -> Result<(), Box<dyn Error>> {
+        self.writer()?.set_dark_mode(enabled)
+    }
+    fn is_system_dark_mode_enabled(&self) -> Result<bool, Box<dyn Error>> {
+        self.is_dark_mode_enabled()
+    }
+    fn set_system_dark_mode(&self, enabled: bool) -> Result<(), Box<dyn Error>> {
+        self.writer()?.set_system_dark_mode(enabled)
+    }
+    fn watch(&self, on_change: Box<dyn Fn() + Send>) -> Result<(), Box<dyn Error>> {
+        watch_color_scheme(&self.connection, move |_| on_change())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::path::Path;
+    use std::process::{Child, Command, Stdio};
+    use std::sync::{mpsc, Mutex};
+    use std::time::Duration;
+    use zbus::blocking::ConnectionBuilder;
+    use zbus::{dbus_interface, fdo};
+
+    // 私有的会话总线，测试结束时关闭
+    struct PrivateBus {
+        daemon: Child,
+        address: String,
+        _dir: tempfile::TempDir,
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.daemon.kill();
+            let _ = self.daemon.wait();
+        }
+    }
+
+    fn private_bus() -> Option<PrivateBus> {
+        let dir = tempfile::tempdir().unwrap();
+        let config = dir.path().join("bus.conf");
+        fs::write(&config, bus_config(dir.path())).unwrap();
+        let mut daemon = match Command::new("dbus-daemon")
+            .arg(format!("--config-file={}", config.display()))
+            .args(["--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(daemon) => daemon,
+            Err(e) => {
+                eprintln!("skipping: cannot start dbus-daemon: {}", e);
+                return None;
+            }
+        };
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        Some(PrivateBus {
+            daemon,
+            address: address.trim().to_owned(),
+            _dir: dir,
+        })
+    }
+
+    fn bus_config(dir: &Path) -> String {
+        format!(
+            r#"<!DOCTYPE busconfig PUBLIC "-//freedesktop//DTD D-Bus Bus Configuration 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/busconfig.dtd">
+<busconfig>
+  <type>session</type>
+  <listen>unix:path={}/bus</listen>
+  <auth>EXTERNAL</auth>
+  <policy context="default">
+    <allow send_destination="*" eavesdrop="true"/>
+    <allow eavesdrop="true"/>
+    <allow own="*"/>
+  </policy>
+</busconfig>
+"#,
+            dir.display()
+        )
+    }
+
+    // 代替真正门户的服务
+    struct FakeSettings {
+        color_scheme: Arc<Mutex<u32>>,
+        legacy: bool,
+    }
+
+    #[dbus_interface(name = "org.freedesktop.portal.Settings")]
+    impl FakeSettings {
+        fn read_one(&self, namespace: &str, key: &str) -> fdo::Result<OwnedValue> {
+            if self.legacy {
+                return Err(fdo::Error::UnknownMethod("ReadOne".to_owned()));
+            }
+            self.lookup(namespace, key).map(OwnedValue::from)
+        }
+
+        fn read(&self, namespace: &str, key: &str) -> fdo::Result<OwnedValue> {
+            let value = self.lookup(namespace, key)?;
+            Ok(Value::Value(Box::new(Value::U32(value))).into())
+        }
+    }
+
+    impl FakeSettings {
+        fn lookup(&self, namespace: &str, key: &str) -> fdo::Result<u32> {
+            if (namespace, key) == (NAMESPACE, KEY) {
+                Ok(*self.color_scheme.lock().unwrap())
+            } else {
+                Err(fdo::Error::Failed("Requested setting not found".to_owned()))
+            }
+        }
+    }
+
+    fn serve(bus: &PrivateBus, legacy: bool) -> (Connection, Arc<Mutex<u32>>) {
+        let color_scheme = Arc::new(Mutex::new(0));
+        let settings = FakeSettings {
+            color_scheme: color_scheme.clone(),
+            legacy,
+        };
+        let service = ConnectionBuilder::address(bus.address.as_str())
+            .unwrap()
+            .name(DESTINATION)
+            .unwrap()
+            .serve_at(PATH, settings)
+            .unwrap()
+            .build()
+            .unwrap();
+        (service, color_scheme)
+    }
+
+    fn client(bus: &PrivateBus) -> Connection {
+        ConnectionBuilder::address(bus.address.as_str())
+            .unwrap()
+            .build()
+            .unwrap()
+    }
+
+    fn emit(service: &Connection, color_scheme: &Mutex<u32>, value: u32) {
+        *color_scheme.lock().unwrap() = value;
+        service
+            .emit_signal(
+                None::<()>,
+                PATH,
+                INTERFACE,
+                "SettingChanged",
+                &(NAMESPACE, KEY, Value::U32(value)),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn reads_color_scheme_from_portal() {
+        let Some(bus) = private_bus() else { return };
+        let (_service, color_scheme) = serve(&bus, false);
+        let backend = PortalBackend::new(client(&bus), None);
+        assert_eq!(backend.color_scheme().unwrap(), ColorScheme::NoPreference);
+        *color_scheme.lock().unwrap() = 1;
+        assert!(backend.is_dark_mode_enabled().unwrap());
+        *color_scheme.lock().unwrap() = 2;
+        assert_eq!(backend.color_scheme().unwrap(), ColorScheme::PreferLight);
+        assert!(!backend.is_system_dark_mode_enabled().unwrap());
+    }
+
+    #[test]
+    fn falls_back_to_legacy_read() {
+        let Some(bus) = private_bus() else { return };
+        let (_service, color_scheme) = serve(&bus, true);
+        *color_scheme.lock().unwrap() = 1;
+        let backend = PortalBackend::new(client(&bus), None);
+        assert_eq!(backend.color_scheme().unwrap(), ColorScheme::PreferDark);
+    }
+
+    #[test]
+    fn writes_go_to_the_desktop_backend() {
+        let Some(bus) = private_bus() else { return };
+        let (_service, _) = serve(&bus, false);
+        let read_only = PortalBackend::new(client(&bus), None);
+        assert!(!read_only.capabilities().write);
+        assert!(read_only.set_dark_mode(true).is_err());
+
+        let writer = Arc::new(MemoryBackend::default());
+        let backend = PortalBackend::new(client(&bus), Some(writer.clone()));
+        assert!(backend.capabilities().write);
+        backend.set_dark_mode(true).unwrap();
+        assert!(writer.is_dark_mode_enabled().unwrap());
+    }
+
+    #[test]
+    fn reacts_to_setting_changed() {
+        let Some(bus) = private_bus() else { return };
+        let (service, color_scheme) = serve(&bus, false);
+        let (sender, receiver) = mpsc::channel();
+        watch_color_scheme(&client(&bus), move |scheme| {
+            sender.send(scheme).unwrap();
+        })
+        .unwrap();
+
+        emit(&service, &color_scheme, 1);
+        emit(&service, &color_scheme, 2);
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            receiver.recv_timeout(timeout).unwrap(),
+            ColorScheme::PreferDark
+        );
+        assert_eq!(
+            receiver.recv_timeout(timeout).unwrap(),
+            ColorScheme::PreferLight
+        );
+    }
+}
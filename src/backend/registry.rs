@@ -0,0 +1,109 @@
+// Windows 注册表后端
+use super::{Capabilities, ThemeBackend};
+use crate::registry::{RegValue, Registry};
+use std::error::Error;
+use std::sync::Arc;
+
+pub const PERSONALIZE_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize";
+
+#[derive(Debug)]
+pub struct RegistryBackend {
+    registry: Arc<dyn Registry>,
+}
+
+impl RegistryBackend {
+    pub fn new(registry: Arc<dyn Registry>) -> Self {
+        Self { registry }
+    }
+
+    // 0 表示深色，1 表示浅色
+    fn read_dark(&self, value_name: &str) -> Result<bool, Box<dyn Error>> {
+        Ok(self.registry.get_dword(PERSONALIZE_KEY, value_name)? == 0)
+    }
+
+    fn write_dark(&self, value_name: &str, enabled: bool) -> Result<(), Box<dyn Error>> {
+        let value = if enabled { 0u32 } else { 1u32 };
+        self.registry
+            .set_value(PERSONALIZE_KEY, value_name, &RegValue::Dword(value))?;
+        Ok(())
+    }
+}
+
+impl ThemeBackend for RegistryBackend {
+    fn name(&self) -> &'static str {
+        "registry"
+    }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            write: true,
+            separate_system_mode: true,
+        }
+    }
+    fn is_dark_mode_enabled(&self) -> Result<bool, Box<dyn Error>> {
+        self.read_dark("AppsUseLightTheme")
+    }
+    fn set_dark_mode(&self, enabled: bool) -> Result<(), Box<dyn Error>> {
+        self.write_dark("AppsUseLightTheme", enabled)
+    }
+    fn is_system_dark_mode_enabled(&self) -> Result<bool, Box<dyn Error>> {
+        self.read_dark("SystemUsesLightTheme")
+    }
+    fn set_system_dark_mode(&self, enabled: bool) -> Result<(), Box<dyn Error>> {
+        self.write_dark("SystemUsesLightTheme", enabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::MemoryRegistry;
+
+    fn personalize_backend() -> (Arc<MemoryRegistry>, RegistryBackend) {
+        let registry = Arc::new(MemoryRegistry::default());
+        registry.create_key(PERSONALIZE_KEY).unwrap();
+        (registry.clone(), RegistryBackend::new(registry))
+    }
+
+    #[test]
+    fn writes_zero_for_dark_and_one_for_light() {
+        let (registry, backend) = personalize_backend();
+        backend.set_dark_mode(true).unwrap();
+        backend.set_system_dark_mode(false).unwrap();
+        assert_eq!(
+            registry
+                .get_value(PERSONALIZE_KEY, "AppsUseLightTheme")
+                .unwrap(),
+            RegValue::Dword(0)
+        );
+        assert_eq!(
+            registry
+                .get_value(PERSONALIZE_KEY, "SystemUsesLightTheme")
+                .unwrap(),
+            RegValue::Dword(1)
+        );
+    }
+
+    #[test]
+    fn reads_app_and_system_values_separately() {
+        let (registry, backend) = personalize_backend();
+        registry
+            .set_value(PERSONALIZE_KEY, "AppsUseLightTheme", &RegValue::Dword(1))
+            .unwrap();
+        registry
+            .set_value(PERSONALIZE_KEY, "SystemUsesLightTheme", &RegValue::Dword(0))
+            .unwrap();
+        assert!(!backend.is_dark_mode_enabled().unwrap());
+        assert!(backend.is_system_dark_mode_enabled().unwrap());
+    }
+
+    #[test]
+    fn missing_key_and_access_errors_are_reported() {
+        let backend = RegistryBackend::new(Arc::new(MemoryRegistry::default()));
+        assert!(backend.is_dark_mode_enabled().is_err());
+        assert!(backend.set_dark_mode(true).is_err());
+
+        let (registry, backend) = personalize_backend();
+        registry.deny_access(PERSONALIZE_KEY);
+        assert!(backend.set_system_dark_mode(true).is_err());
+    }
+}
@@ -0,0 +1,510 @@
+// 命令行子命令，用于脚本和 SSH 会话，不打开窗口
+use crate::backend::ThemeBackend;
+use crate::ipc::{Client, Request};
+use crate::MyApp;
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::Write;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "用法：
+  autodark                                   打开设置窗口，已经在运行时显示它的窗口
+  autodark --daemon                          不打开窗口，在后台按计划切换
+  autodark set <dark|light> [--target 目标]  设置深色或浅色模式
+  autodark toggle [--target 目标]            切换深浅模式
+  autodark status [--json]                   显示当前模式和自动切换的设置
+  autodark next [--json]                     显示下一次定时切换
+  autodark pause                             暂停正在运行的实例的自动切换
+  autodark resume                            恢复正在运行的实例的自动切换
+  autodark reload                            让正在运行的实例重新读取配置
+所有命令都可以用 --config <文件> 指定配置文件。
+有实例在运行时命令交给它执行，否则直接修改主题。
+目标可以是 app(默认应用模式)、system(默认windows模式)或 both(默认)。
+Windows 上命令的输出显示在启动它的终端中，但 cmd 不会等命令结束，
+脚本中需要退出码时请用 start /wait autodark <命令>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Target {
+    App,
+    System,
+    Both,
+}
+
+impl Target {
+    pub fn app(&self) -> bool {
+        matches!(self, Target::App | Target::Both)
+    }
+
+    pub fn system(&self) -> bool {
+        matches!(self, Target::System | Target::Both)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Set { dark: bool, target: Target },
+    Toggle { target: Target },
+    Status { json: bool },
+    Next { json: bool },
+    // 以下命令只能交给正在运行的实例
+    Pause,
+    Resume,
+    Reload,
+    Help,
+}
+
+// 程序的运行方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // 打开设置窗口
+    Gui,
+    // 不打开窗口，在后台按计划切换
+    Daemon,
+    // 执行一个命令后退出
+    Command(Command),
+}
+
+// 取出所有命令都能用的 --config 选项，剩下的参数交给 parse
+pub fn take_config(args: &[String]) -> Result<(Option<PathBuf>, Vec<String>), String> {
+    let mut config = None;
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            let path = args.next().ok_or("--config 后面缺少配置文件的路径")?;
+            config = Some(PathBuf::from(path));
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            config = Some(PathBuf::from(path));
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    Ok((config, rest))
+}
+
+pub fn parse(args: &[String]) -> Result<Mode, String> {
+    let Some((name, rest)) = args.split_first() else {
+        return Ok(Mode::Gui);
+    };
+    let mut target = Target::Both;
+    let mut json = false;
+    let mut positional = Vec::new();
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--target" | "-t" => {
+                target = match rest.next().map(String::as_str) {
+                    Some("app") => Target::App,
+                    Some("system") => Target::System,
+                    Some("both") => Target::Both,
+                    Some(other) => {
+                        return Err(format!("未知的目标 {}，可用的有 app、system、both", other))
+                    }
+                    None => return Err("--target 后面缺少目标".to_owned()),
+                }
+            }
+            "--json" => json = true,
+            other if other.starts_with('-') => return Err(format!("未知的选项 {}", other)),
+            other => positional.push(other),
+        }
+    }
+    let command = match (name.as_str(), positional.as_slice()) {
+        ("set", [mode]) => Command::Set {
+            dark: match *mode {
+                "dark" => true,
+                "light" => false,
+                other => return Err(format!("未知的模式 {}，应为 dark 或 light", other)),
+            },
+            target,
+        },
+        ("set", _) => return Err("set 需要一个模式：dark 或 light".to_owned()),
+        ("toggle", []) => Command::Toggle { target },
+        ("status", []) => Command::Status { json },
+        ("next", []) => Command::Next { json },
+        ("pause", []) => Command::Pause,
+        ("resume", []) => Command::Resume,
+        ("reload", []) => Command::Reload,
+        ("--daemon" | "daemon", []) => return Ok(Mode::Daemon),
+        ("help" | "--help" | "-h", _) => Command::Help,
+        (
+            "toggle" | "status" | "next" | "pause" | "resume" | "reload" | "--daemon" | "daemon",
+            [extra, ..],
+        ) => return Err(format!("{} 不需要参数 {}", name, extra)),
+        (other, _) => return Err(format!("未知的命令 {}", other)),
+    };
+    Ok(Mode::Command(command))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub backend: String,
+    pub app_dark: Option<bool>,
+    pub system_dark: Option<bool>,
+    pub auto_app: bool,
+    pub auto_system: bool,
+    pub system_follows_app: bool,
+    // 正在运行的实例是否暂停了自动切换
+    #[serde(default)]
+    pub paused: bool,
+    #[serde(default)]
+    pub next: Next,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Next {
+    // 没有安排切换时为 None
+    pub at: Option<String>,
+    pub app_dark: Option<bool>,
+    pub system_dark: Option<bool>,
+}
+
+// 当前模式、自动切换的设置和下一次切换
+pub fn status(config: &MyApp, backend: &dyn ThemeBackend, paused: bool) -> Status {
+    let now = Local::now().naive_local();
+    let plan = config.plan();
+    let at = plan.next_transition(now);
+    let (app_dark, system_dark) = at.map_or((None, None), |at| plan.desired(at));
+    Status {
+        backend: backend.name().to_owned(),
+        app_dark: backend.is_dark_mode_enabled().ok(),
+        system_dark: backend.is_system_dark_mode_enabled().ok(),
+        auto_app: config.auto_mode_change,
+        auto_system: config.auto_system_mode_change,
+        system_follows_app: config.is_system_both_dark_mode,
+        paused,
+        next: Next {
+            at: at.map(format_time),
+            app_dark,
+            system_dark,
+        },
+    }
+}
+
+// 没有实例在运行时直接读写主题
+pub fn run(
+    command: Command,
+    config: &MyApp,
+    backend: &dyn ThemeBackend,
+    out: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Set { dark, target } => set(backend, target, dark),
+        Command::Toggle { target } => {
+            // 以默认应用模式为准，只切换系统模式时以系统模式为准
+            let current = match target {
+                Target::System => backend.is_system_dark_mode_enabled()?,
+                _ => backend.is_dark_mode_enabled()?,
+            };
+            set(backend, target, !current)?;
+            writeln!(out, "{}", mode_label(!current))?;
+            Ok(())
+        }
+        Command::Status { json } => print_status(&status(config, backend, false), json, out),
+        Command::Next { json } => print_next(&status(config, backend, false).next, json, out),
+        Command::Pause | Command::Resume | Command::Reload => Err("没有正在运行的实例".into()),
+        Command::Help => {
+            writeln!(out, "{}", USAGE)?;
+            Ok(())
+        }
+    }
+}
+
+// 交给正在运行的实例执行，这样它的定时计划和手动切换也会更新
+pub fn run_remote(
+    command: Command,
+    client: &mut Client,
+    out: &mut dyn Write,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Set { dark, target } => {
+            client.request(&Request::Set { dark, target })?;
+            Ok(())
+        }
+        Command::Toggle { target } => {
+            let status = client.status()?;
+            let current = match target {
+                Target::System => status.system_dark,
+                _ => status.app_dark,
+            }
+            .ok_or("无法读取当前模式")?;
+            client.request(&Request::Set {
+                dark: !current,
+                target,
+            })?;
+            writeln!(out, "{}", mode_label(!current))?;
+            Ok(())
+        }
+        Command::Status { json } => print_status(&client.status()?, json, out),
+        Command::Next { json } => print_next(&client.status()?.next, json, out),
+        Command::Pause => {
+            client.request(&Request::Pause)?;
+            writeln!(out, "已暂停自动切换")?;
+            Ok(())
+        }
+        Command::Resume => {
+            client.request(&Request::Resume)?;
+            writeln!(out, "已恢复自动切换")?;
+            Ok(())
+        }
+        Command::Reload => {
+            client.request(&Request::Reload)?;
+            writeln!(out, "已重新读取配置")?;
+            Ok(())
+        }
+        Command::Help => {
+            writeln!(out, "{}", USAGE)?;
+            Ok(())
+        }
+    }
+}
+
+fn print_status(status: &Status, json: bool, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    if json {
+        writeln!(out, "{}", serde_json::to_string(status)?)?;
+        return Ok(());
+    }
+    let on_off = |enabled: bool| if enabled { "已启用" } else { "未启用" };
+    let dark = |dark: Option<bool>| dark.map_or("未知", mode_label);
+    writeln!(out, "后端：{}", status.backend)?;
+    writeln!(out, "默认应用模式：{}", dark(status.app_dark))?;
+    writeln!(out, "默认windows模式：{}", dark(status.system_dark))?;
+    writeln!(out, "自动切换默认应用模式：{}", on_off(status.auto_app))?;
+    writeln!(
+        out,
+        "自动切换默认windows模式：{}",
+        on_off(status.auto_system)
+    )?;
+    if status.paused {
+        writeln!(out, "自动切换已暂停")?;
+    }
+    if let Some(next) = &status.next.at {
+        writeln!(out, "下一次切换：{}", next)?;
+    }
+    Ok(())
+}
+
+fn print_next(next: &Next, json: bool, out: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    if json {
+        writeln!(out, "{}", serde_json::to_string(next)?)?;
+        return Ok(());
+    }
+    let Some(at) = &next.at else {
+        writeln!(out, "没有安排切换")?;
+        return Ok(());
+    };
+    let mut changes = Vec::new();
+    if let Some(dark) = next.app_dark {
+        changes.push(format!("默认应用模式切换为{}", mode_label(dark)));
+    }
+    if let Some(dark) = next.system_dark {
+        changes.push(format!("默认windows模式切换为{}", mode_label(dark)));
+    }
+    writeln!(out, "{}：{}", at, changes.join("，"))?;
+    Ok(())
+}
+
+// 检查后端能否修改目标的模式
+pub fn check_writable(backend: &dyn ThemeBackend, target: Target) -> Result<(), Box<dyn Error>> {
+    let capabilities = backend.capabilities();
+    if !capabilities.write {
+        return Err(format!("后端 {} 不能修改主题", backend.name()).into());
+    }
+    if target == Target::System && !capabilities.separate_system_mode {
+        return Err(format!("后端 {} 不能单独设置默认windows模式", backend.name()).into());
+    }
+    Ok(())
+}
+
+fn set(backend: &dyn ThemeBackend, target: Target, dark: bool) -> Result<(), Box<dyn Error>> {
+    check_writable(backend, target)?;
+    if target.app() {
+        backend.set_dark_mode(dark)?;
+    }
+    // 只有一个配色设置的后端设置应用模式时已经一起修改
+    if target.system() && backend.capabilities().separate_system_mode {
+        backend.set_system_dark_mode(dark)?;
+    }
+    Ok(())
+}
+fn mode_label(dark: bool) -> &'static str {
+    if dark {
+        "深色模式"
+    } else {
+        "浅色模式"
+    }
+}
+
+fn format_time(time: NaiveDateTime) -> String {
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::DarkWindow;
+
+    fn parse_args(args: &str) -> Result<Mode, String> {
+        let args: Vec<String> = args.split_whitespace().map(str::to_owned).collect();
+        parse(&args)
+    }
+
+    fn output(command: Command, config: &MyApp, backend: &MemoryBackend) -> String {
+        let mut out = Vec::new();
+        run(command, config, backend, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn parses_subcommands() {
+        assert_eq!(parse_args(""), Ok(Mode::Gui));
+        assert_eq!(parse_args("--daemon"), Ok(Mode::Daemon));
+        assert_eq!(
+            parse_args("set dark --target app"),
+            Ok(Mode::Command(Command::Set {
+                dark: true,
+                target: Target::App
+            }))
+        );
+        assert_eq!(
+            parse_args("set -t system light"),
+            Ok(Mode::Command(Command::Set {
+                dark: false,
+                target: Target::System
+            }))
+        );
+        assert_eq!(
+            parse_args("toggle"),
+            Ok(Mode::Command(Command::Toggle {
+                target: Target::Both
+            }))
+        );
+        assert_eq!(
+            parse_args("status --json"),
+            Ok(Mode::Command(Command::Status { json: true }))
+        );
+        assert_eq!(
+            parse_args("next"),
+            Ok(Mode::Command(Command::Next { json: false }))
+        );
+        assert_eq!(parse_args("--help"), Ok(Mode::Command(Command::Help)));
+        assert_eq!(parse_args("pause"), Ok(Mode::Command(Command::Pause)));
+        assert_eq!(
+            parse_args("reload now"),
+            Err("reload 不需要参数 now".to_owned())
+        );
+
+        assert_eq!(
+            parse_args("set dim"),
+            Err("未知的模式 dim，应为 dark 或 light".to_owned())
+        );
+        assert_eq!(
+            parse_args("set"),
+            Err("set 需要一个模式：dark 或 light".to_owned())
+        );
+        assert_eq!(
+            parse_args("set dark --target"),
+            Err("--target 后面缺少目标".to_owned())
+        );
+        assert_eq!(
+            parse_args("toggle --target taskbar"),
+            Err("未知的目标 taskbar，可用的有 app、system、both".to_owned())
+        );
+        assert_eq!(
+            parse_args("status --yaml"),
+            Err("未知的选项 --yaml".to_owned())
+        );
+        assert_eq!(parse_args("start"), Err("未知的命令 start".to_owned()));
+    }
+
+    #[test]
+    fn config_flag_works_with_every_command() {
+        let args =
+            |args: &str| -> Vec<String> { args.split_whitespace().map(str::to_owned).collect() };
+        assert_eq!(
+            take_config(&args("--config /tmp/a.toml status --json")),
+            Ok((Some(PathBuf::from("/tmp/a.toml")), args("status --json")))
+        );
+        assert_eq!(
+            take_config(&args("--daemon --config=b.toml")),
+            Ok((Some(PathBuf::from("b.toml")), args("--daemon")))
+        );
+        assert_eq!(take_config(&args("toggle")), Ok((None, args("toggle"))));
+        assert_eq!(
+            take_config(&args("status --config")),
+            Err("--config 后面缺少配置文件的路径".to_owned())
+        );
+    }
+
+    #[test]
+    fn set_and_toggle_write_the_backend() {
+        let backend = MemoryBackend::default();
+        let config = MyApp::default();
+        output(
+            Command::Set {
+                dark: true,
+                target: Target::App,
+            },
+            &config,
+            &backend,
+        );
+        assert!(backend.is_dark_mode_enabled().unwrap());
+        assert!(!backend.is_system_dark_mode_enabled().unwrap());
+
+        let printed = output(
+            Command::Toggle {
+                target: Target::Both,
+            },
+            &config,
+            &backend,
+        );
+        assert_eq!(printed, "浅色模式\n");
+        assert!(!backend.is_dark_mode_enabled().unwrap());
+        assert!(!backend.is_system_dark_mode_enabled().unwrap());
+
+        // 暂停等命令需要正在运行的实例
+        let mut out = Vec::new();
+        let error = run(Command::Pause, &config, &backend, &mut out).unwrap_err();
+        assert_eq!(error.to_string(), "没有正在运行的实例");
+    }
+
+    #[test]
+    fn status_and_next_as_json() {
+        let backend = MemoryBackend::default();
+        backend.set_system_dark_mode(true).unwrap();
+        let config = MyApp {
+            auto_mode_change: true,
+            night_windows: vec![DarkWindow::new(18, 8)],
+            ..Default::default()
+        };
+        let status: serde_json::Value =
+            serde_json::from_str(&output(Command::Status { json: true }, &config, &backend))
+                .unwrap();
+        assert_eq!(status["backend"], "memory");
+        assert_eq!(status["app_dark"], false);
+        assert_eq!(status["system_dark"], true);
+        assert_eq!(status["auto_app"], true);
+        assert_eq!(status["auto_system"], false);
+        assert!(status["next"]["at"].is_string());
+        assert!(status.get("next_transition").is_none());
+
+        let next: serde_json::Value =
+            serde_json::from_str(&output(Command::Next { json: true }, &config, &backend)).unwrap();
+        let at = next["at"].as_str().unwrap();
+        // 下一次切换是 18:00 变深或 08:00 变浅
+        let dark = next["app_dark"].as_bool().unwrap();
+        assert!(
+            at.ends_with(if dark { "18:00:00" } else { "08:00:00" }),
+            "{}",
+            at
+        );
+        assert!(next["system_dark"].is_null());
+
+        assert_eq!(
+            output(Command::Next { json: false }, &MyApp::default(), &backend),
+            "没有安排切换\n"
+        );
+    }
+}
@@ -0,0 +1,477 @@
+// 配置文件：保存的设置、旧版本配置的转换，以及文件的位置
+use crate::backend::BackendSettings;
+use crate::exceptions::DateException;
+use crate::manual::{ExternalChangePolicy, Override, OverrideSettings};
+use crate::solar::{Location, SolarSettings};
+use crate::{DarkWindow, MyApp, SeasonSettings, WeekdaySettings};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// 当前的配置版本。结构有变化时加一，并在 MIGRATIONS 最后加上从上一版转换的步骤
+pub const VERSION: u32 = 2;
+
+// MIGRATIONS[n] 把第 n 版转换为第 n + 1 版
+const MIGRATIONS: [fn(&mut toml::Table); VERSION as usize] =
+    [split_single_windows, group_by_target];
+
+// 保存在配置文件中的设置，界面上的状态在 MyApp 中
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub version: u32,
+    pub autostart: bool,
+    // 默认windows模式(任务栏)和默认应用模式(常规应用)设为相同
+    pub system_follows_app: bool,
+    // 位置的来源(城市名或时区)，为空表示手动输入
+    pub location_name: String,
+    pub location: Location,
+    pub override_settings: OverrideSettings,
+    pub external_change_policy: ExternalChangePolicy,
+    // 节假日等特殊日期，两个目标共用
+    pub date_exceptions: Vec<DateException>,
+    pub backend: BackendSettings,
+    // 默认应用模式(常规应用)
+    pub app: TargetConfig,
+    // 默认windows模式(任务栏)
+    pub system: TargetConfig,
+}
+
+// 一个目标的模式和自动切换设置
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TargetConfig {
+    pub dark: bool,
+    pub auto: bool,
+    pub windows: Vec<DarkWindow>,
+    pub weekdays: WeekdaySettings,
+    pub seasons: SeasonSettings,
+    pub solar: SolarSettings,
+    #[serde(rename = "override")]
+    pub manual: Option<Override>,
+}
+
+impl From<&MyApp> for Config {
+    fn from(app: &MyApp) -> Self {
+        Self {
+            version: VERSION,
+            autostart: app.is_autostart,
+            system_follows_app: app.is_system_both_dark_mode,
+            location_name: app.location_name.clone(),
+            location: app.location,
+            override_settings: app.override_settings,
+            external_change_policy: app.external_change_policy,
+            date_exceptions: app.date_exceptions.clone(),
+            backend: app.backend_settings.clone(),
+            app: TargetConfig {
+                dark: app.is_dark_mode,
+                auto: app.auto_mode_change,
+                windows: app.night_windows.clone(),
+                weekdays: app.app_weekdays.clone(),
+                seasons: app.app_seasons.clone(),
+                solar: app.app_solar,
+                manual: app.app_override,
+            },
+            system: TargetConfig {
+                dark: app.is_system_dark_mode,
+                auto: app.auto_system_mode_change,
+                windows: app.system_night_windows.clone(),
+                weekdays: app.system_weekdays.clone(),
+                seasons: app.system_seasons.clone(),
+                solar: app.system_solar,
+                manual: app.system_override,
+            },
+        }
+    }
+}
+
+impl From<Config> for MyApp {
+    fn from(config: Config) -> Self {
+        Self {
+            is_dark_mode: config.app.dark,
+            is_system_dark_mode: config.system.dark,
+            is_system_both_dark_mode: config.system_follows_app,
+            auto_mode_change: config.app.auto,
+            auto_system_mode_change: config.system.auto,
+            night_windows: config.app.windows,
+            system_night_windows: config.system.windows,
+            app_weekdays: config.app.weekdays,
+            system_weekdays: config.system.weekdays,
+            app_seasons: config.app.seasons,
+            system_seasons: config.system.seasons,
+            app_override: config.app.manual,
+            system_override: config.system.manual,
+            override_settings: config.override_settings,
+            external_change_policy: config.external_change_policy,
+            date_exceptions: config.date_exceptions,
+            is_autostart: config.autostart,
+            location: config.location,
+            location_name: config.location_name,
+            app_solar: config.app.solar,
+            system_solar: config.system.solar,
+            backend_settings: config.backend,
+            ..Default::default()
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Result<Config, Box<dyn Error>> {
+    parse(&fs::read_to_string(path)?)
+}
+
+// 旧版本的配置逐步转换为当前版本后再读取
+pub fn parse(text: &str) -> Result<Config, Box<dyn Error>> {
+    let mut table: toml::Table = toml::from_str(text)?;
+    let version = version_of(&table)?;
+    if version > VERSION {
+        return Err(format!(
+            "配置文件的版本 {} 比程序支持的版本 {} 新，请更新程序",
+            version, VERSION
+        )
+        .into());
+    }
+    for migrate in &MIGRATIONS[version as usize..] {
+        migrate(&mut table);
+    }
+    table.insert("version".to_owned(), i64::from(VERSION).into());
+    Ok(table.try_into()?)
+}
+
+// 没有版本号的旧版本配置一定有这些字段，用来区分其他程序的 TOML 文件
+const LEGACY_KEYS: [&str; 6] = [
+    "is_dark_mode",
+    "is_system_dark_mode",
+    "is_system_both_dark_mode",
+    "auto_mode_change",
+    "auto_system_mode_change",
+    "is_autostart",
+];
+
+// 没有版本号的是直接保存 MyApp 的平铺格式：时间段列表是第 1 版，只有一个时间段的是第 0 版
+fn version_of(table: &toml::Table) -> Result<u32, String> {
+    match table.get("version") {
+        Some(toml::Value::Integer(version)) => {
+            return u32::try_from(*version).map_err(|_| format!("无效的配置版本 {}", version))
+        }
+        Some(other) => return Err(format!("无效的配置版本 {}", other)),
+        None => {}
+    }
+    if let Some(key) = LEGACY_KEYS.iter().find(|key| !table.contains_key(**key)) {
+        return Err(format!(
+            "不是本程序的配置文件：既没有 version，也没有旧版本的 {}",
+            key
+        ));
+    }
+    let lists = table.contains_key("night_windows") || table.contains_key("system_night_windows");
+    Ok(if lists { 1 } else { 0 })
+}
+
+// 第 0 版到第 1 版：只有一个深色时间段(xxx_start/xxx_end，以及重复的时和分)，转换为时间段列表
+fn split_single_windows(table: &mut toml::Table) {
+    split_single_window(table, "custom_night", "night_windows");
+    split_single_window(table, "custom_system_night", "system_night_windows");
+}
+
+fn split_single_window(table: &mut toml::Table, prefix: &str, key: &str) {
+    let start = table.remove(&format!("{}_start", prefix));
+    let end = table.remove(&format!("{}_end", prefix));
+    for field in ["start_hh", "start_mm", "end_hh", "end_mm"] {
+        table.remove(&format!("{}_{}", prefix, field));
+    }
+    if let (Some(start), Some(end)) = (start, end) {
+        if !table.contains_key(key) {
+            let mut window = toml::Table::new();
+            window.insert("start".to_owned(), start);
+            window.insert("end".to_owned(), end);
+            table.insert(key.to_owned(), vec![toml::Value::Table(window)].into());
+        }
+    }
+}
+
+// 第 1 版到第 2 版：两个目标各自的设置放到 [app] 和 [system] 中
+fn group_by_target(table: &mut toml::Table) {
+    const RENAMED: [(&str, &str); 3] = [
+        ("is_autostart", "autostart"),
+        ("is_system_both_dark_mode", "system_follows_app"),
+        ("backend_settings", "backend"),
+    ];
+    const GROUPED: [(&str, &str, &str); 14] = [
+        ("is_dark_mode", "app", "dark"),
+        ("auto_mode_change", "app", "auto"),
+        ("night_windows", "app", "windows"),
+        ("app_weekdays", "app", "weekdays"),
+        ("app_seasons", "app", "seasons"),
+        ("app_solar", "app", "solar"),
+        ("app_override", "app", "override"),
+        ("is_system_dark_mode", "system", "dark"),
+        ("auto_system_mode_change", "system", "auto"),
+        ("system_night_windows", "system", "windows"),
+        ("system_weekdays", "system", "weekdays"),
+        ("system_seasons", "system", "seasons"),
+        ("system_solar", "system", "solar"),
+        ("system_override", "system", "override"),
+    ];
+    for (from, to) in RENAMED {
+        if let Some(value) = table.remove(from) {
+            table.insert(to.to_owned(), value);
+        }
+    }
+    for (from, target, key) in GROUPED {
+        let Some(value) = table.remove(from) else {
+            continue;
+        };
+        let group = table
+            .entry(target)
+            .or_insert_with(|| toml::Table::new().into());
+        if let Some(group) = group.as_table_mut() {
+            group.insert(key.to_owned(), value);
+        }
+    }
+}
+
+// 读取失败的配置文件会被默认设置覆盖，先复制一份，返回副本的路径
+pub fn back_up(path: &Path) -> io::Result<PathBuf> {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    let backup = PathBuf::from(backup);
+    fs::copy(path, &backup)?;
+    Ok(backup)
+}
+
+// 配置文件的位置：--config 指定的文件，便携模式下在程序旁边，否则在用户的配置目录里
+pub const FILE_NAME: &str = "config.toml";
+// 程序所在目录有这个文件时为便携模式
+pub const PORTABLE_MARKER: &str = "portable";
+const APP_DIR: &str = "autodark";
+
+// 配置文件的位置是怎么确定的
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Flag,
+    Portable,
+    User,
+    // 找不到用户的配置目录时使用当前目录
+    WorkingDir,
+}
+
+pub fn locate(flag: Option<PathBuf>) -> (PathBuf, Source) {
+    let exe = env::current_exe().ok();
+    locate_in(
+        flag,
+        exe.as_deref().and_then(Path::parent),
+        user_config_dir(),
+    )
+}
+
+fn locate_in(
+    flag: Option<PathBuf>,
+    exe_dir: Option<&Path>,
+    user_dir: Option<PathBuf>,
+) -> (PathBuf, Source) {
+    if let Some(path) = flag {
+        return (path, Source::Flag);
+    }
+    if let Some(dir) = exe_dir.filter(|dir| dir.join(PORTABLE_MARKER).exists()) {
+        return (dir.join(FILE_NAME), Source::Portable);
+    }
+    match user_dir {
+        Some(dir) => (dir.join(APP_DIR).join(FILE_NAME), Source::User),
+        None => (PathBuf::from(FILE_NAME), Source::WorkingDir),
+    }
+}
+
+#[cfg(windows)]
+fn user_config_dir() -> Option<PathBuf> {
+    env::var_os("APPDATA")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+}
+
+#[cfg(not(windows))]
+fn user_config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+}
+
+// 以前的版本使用的配置文件：当前目录里的，以及程序旁边的
+pub fn legacy_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(FILE_NAME)];
+    if let Some(dir) = env::current_exe().ok().as_deref().and_then(Path::parent) {
+        paths.push(dir.join(FILE_NAME));
+    }
+    paths
+}
+
+// 当前目录里的 config.toml 也可能属于其他程序
+pub fn is_legacy_config(text: &str) -> bool {
+    text.parse::<toml::Table>()
+        .is_ok_and(|table| !table.contains_key("version") && version_of(&table).is_ok())
+}
+
+// 以前的版本把配置保存在当前目录，第一次运行时复制到新的位置，返回是否复制了。
+// 原来的文件保留不动
+pub fn migrate_legacy(legacy: &Path, path: &Path) -> io::Result<bool> {
+    if path.exists() || !legacy.is_file() {
+        return Ok(false);
+    }
+    if !fs::read_to_string(legacy).is_ok_and(|text| is_legacy_config(&text)) {
+        return Ok(false);
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::copy(legacy, path)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 每一种保存过的配置格式，设置 UPDATE_GOLDEN=1 时重新生成期望的结果
+    #[test]
+    fn historical_configs_match_the_golden_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/config");
+        for name in ["v0-baseline", "v0-solar", "v1-windows", "v1-full"] {
+            let input = fs::read_to_string(dir.join(format!("{}.toml", name))).unwrap();
+            let config = parse(&input).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            let migrated = toml::to_string_pretty(&config).unwrap();
+            let golden = dir.join(format!("{}.expected.toml", name));
+            if env::var_os("UPDATE_GOLDEN").is_some() {
+                fs::write(&golden, &migrated).unwrap();
+            }
+            assert_eq!(migrated, fs::read_to_string(&golden).unwrap(), "{}", name);
+            // 转换后的结果再读取时不变
+            assert_eq!(parse(&migrated).unwrap(), config, "{}", name);
+        }
+        // 其他程序的 TOML 文件和不完整的旧配置不能当作默认设置读取
+        for (name, key) in [
+            ("unrelated-hugo", "is_dark_mode"),
+            ("unrelated-partial", "is_system_dark_mode"),
+        ] {
+            let input = fs::read_to_string(dir.join(format!("{}.toml", name))).unwrap();
+            assert_eq!(
+                parse(&input).unwrap_err().to_string(),
+                format!(
+                    "不是本程序的配置文件：既没有 version，也没有旧版本的 {}",
+                    key
+                ),
+                "{}",
+                name
+            );
+            assert!(!is_legacy_config(&input), "{}", name);
+        }
+        assert!(parse("").is_err());
+
+        // 当前版本原样读取和保存
+        let current = fs::read_to_string(dir.join("v2.toml")).unwrap();
+        let config = parse(&current).unwrap();
+        assert_eq!(config.version, VERSION);
+        assert_eq!(toml::to_string_pretty(&config).unwrap(), current);
+    }
+
+    #[test]
+    fn migrated_settings_reach_the_app() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/config");
+        let app = MyApp::from(load(&dir.join("v0-baseline.toml")).unwrap());
+        assert!(app.is_dark_mode && app.auto_mode_change && app.is_autostart);
+        assert_eq!(app.night_windows.len(), 1);
+        assert_eq!(app.night_windows[0].start.to_string(), "19:30:00");
+        assert_eq!(app.system_night_windows, [DarkWindow::new(18, 8)]);
+
+        let app = MyApp::from(load(&dir.join("v1-full.toml")).unwrap());
+        assert_eq!(app.system_seasons.seasons.len(), 2);
+        assert!(app.app_override.and_then(|m| m.until).is_some());
+        assert_eq!(app.external_change_policy, ExternalChangePolicy::Reassert);
+        assert_eq!(Config::from(&app), load(&dir.join("v2.toml")).unwrap());
+    }
+
+    #[test]
+    fn unsupported_versions_are_rejected() {
+        let error = parse("version = 99\n").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "配置文件的版本 99 比程序支持的版本 2 新，请更新程序"
+        );
+        assert_eq!(
+            parse("version = \"2\"\n").unwrap_err().to_string(),
+            "无效的配置版本 \"2\""
+        );
+        // 缺少的字段使用默认值
+        let config = parse("version = 2\n[app]\nauto = true\n").unwrap();
+        assert!(config.app.auto);
+        assert_eq!(config.system, TargetConfig::default());
+    }
+
+    #[test]
+    fn flag_wins_over_portable_mode_and_user_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe_dir = dir.path().join("bin");
+        let user_dir = dir.path().join("home/.config");
+        fs::create_dir_all(&exe_dir).unwrap();
+        let locate = |flag: Option<&str>| {
+            locate_in(
+                flag.map(PathBuf::from),
+                Some(&exe_dir),
+                Some(user_dir.clone()),
+            )
+        };
+
+        assert_eq!(
+            locate(None),
+            (user_dir.join("autodark/config.toml"), Source::User)
+        );
+        fs::write(exe_dir.join(PORTABLE_MARKER), "").unwrap();
+        assert_eq!(
+            locate(None),
+            (exe_dir.join("config.toml"), Source::Portable)
+        );
+        assert_eq!(
+            locate(Some("other.toml")),
+            (PathBuf::from("other.toml"), Source::Flag)
+        );
+        assert_eq!(
+            locate_in(None, None, None),
+            (PathBuf::from("config.toml"), Source::WorkingDir)
+        );
+    }
+
+    #[test]
+    fn legacy_config_is_copied_only_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let legacy = dir.path().join("config.toml");
+        let path = dir.path().join("home/autodark/config.toml");
+        assert!(!migrate_legacy(&legacy, &path).unwrap());
+
+        // 其他程序的 config.toml 不动
+        let hugo = "baseURL = \"https://example.org/\"\ntitle = \"博客\"\n\n[params]\nis_dark_mode = true\n";
+        fs::write(&legacy, hugo).unwrap();
+        assert!(!migrate_legacy(&legacy, &path).unwrap());
+        assert!(!path.exists());
+        fs::write(&legacy, "is_dark_mode = true\n").unwrap();
+        assert!(!migrate_legacy(&legacy, &path).unwrap());
+
+        let old = fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/config/v0-baseline.toml"),
+        )
+        .unwrap();
+        fs::write(&legacy, &old).unwrap();
+        assert!(migrate_legacy(&legacy, &path).unwrap());
+        assert_eq!(fs::read_to_string(&legacy).unwrap(), old);
+        assert_eq!(fs::read_to_string(&path).unwrap(), old);
+
+        // 新位置已经有配置时不覆盖
+        fs::write(
+            &legacy,
+            old.replace("is_dark_mode = true", "is_dark_mode = false"),
+        )
+        .unwrap();
+        assert!(!migrate_legacy(&legacy, &path).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), old);
+    }
+}
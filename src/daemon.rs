@@ -0,0 +1,163 @@
+// 没有界面的后台模式：按计划切换主题，处理控制接口的请求和外部的修改，收到 SIGTERM 或 SIGINT 时退出
+use crate::backend::ThemeBackend;
+use crate::ipc::{self, Instance, Server};
+use crate::MyApp;
+use signal_hook::consts::signal::{SIGINT, SIGTERM};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// 检查退出信号和外部修改的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+pub fn run(config: MyApp, backend: Arc<dyn ThemeBackend>) -> Result<(), Box<dyn Error>> {
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, stop.clone())?;
+    }
+    // 同时只能有一个实例在切换主题
+    let server = match ipc::claim(ipc::endpoint()) {
+        Ok(Instance::First(server)) => Some(server),
+        Ok(Instance::Running(_)) => return Err("已经有实例在运行".into()),
+        Err(e) => {
+            log::warn!("Failed to start control interface: {}", e);
+            None
+        }
+    };
+    run_until(config, backend, server.as_ref(), &stop);
+    Ok(())
+}
+
+// 运行定时线程并处理控制请求，直到 stop 被设置
+fn run_until(
+    config: MyApp,
+    backend: Arc<dyn ThemeBackend>,
+    server: Option<&Server>,
+    stop: &AtomicBool,
+) {
+    let plan = config.plan();
+    log::info!(
+        "Daemon started with backend {}, app automation {}, system automation {}",
+        backend.name(),
+        plan.app.is_some(),
+        plan.system.is_some() || plan.system_follows_app
+    );
+    if let Some(server) = server {
+        log::info!(
+            "Listening for control requests on {}",
+            server.path().display()
+        );
+    }
+    let mut app = MyApp::start(config, backend, || {});
+    while !stop.load(Ordering::Relaxed) {
+        match server {
+            Some(server) => {
+                if let Some(incoming) = server.recv_timeout(POLL_INTERVAL) {
+                    log::debug!("Control request: {:?}", incoming.request);
+                    let response = app.handle_request(incoming.request);
+                    incoming.reply(response);
+                }
+            }
+            None => thread::sleep(POLL_INTERVAL),
+        }
+        // 和界面一样处理外部修改，保存手动切换
+        app.sync();
+    }
+    log::info!("Received stop signal, shutting down");
+    drop(app);
+    log::info!("Daemon stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use crate::cli::Target;
+    use crate::ipc::{Client, Request};
+    use crate::manual::ExternalChangePolicy;
+    use crate::DarkWindow;
+
+    // 等 check 成立，最多一秒
+    fn wait_for(check: impl Fn() -> bool) -> bool {
+        for _ in 0..50 {
+            if check() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        check()
+    }
+
+    #[test]
+    fn applies_the_plan_and_stops_when_asked() {
+        let backend = Arc::new(MemoryBackend::default());
+        let dir = tempfile::tempdir().unwrap();
+        let config = MyApp {
+            auto_mode_change: true,
+            is_system_both_dark_mode: true,
+            // 全天深色
+            night_windows: vec![DarkWindow::new(0, 12), DarkWindow::new(12, 0)],
+            config_path: dir.path().join("config.toml"),
+            ..Default::default()
+        };
+        let server = Server::bind(dir.path().join("autodark.sock")).unwrap();
+        let mut client = Client::connect(server.path()).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread_backend = backend.clone();
+        let daemon =
+            thread::spawn(move || run_until(config, thread_backend, Some(&server), &thread_stop));
+        // 等定时线程第一次切换
+        assert!(wait_for(|| backend.is_dark_mode_enabled().unwrap()));
+        assert!(backend.is_system_dark_mode_enabled().unwrap());
+
+        // 通过控制接口切换时保持到手动取消，因为全天深色的计划不会再切换
+        client
+            .request(&Request::Set {
+                dark: false,
+                target: Target::App,
+            })
+            .unwrap();
+        assert!(!backend.is_dark_mode_enabled().unwrap());
+        assert!(!backend.is_system_dark_mode_enabled().unwrap());
+        client.request(&Request::Pause).unwrap();
+        let status = client.status().unwrap();
+        assert!(status.paused);
+        assert_eq!(status.app_dark, Some(false));
+        // 手动切换保存在配置文件中，重新启动后仍然有效
+        let config_path = dir.path().join("config.toml");
+        assert!(wait_for(|| std::fs::read_to_string(&config_path)
+            .unwrap_or_default()
+            .contains("[app.override]")));
+
+        stop.store(true, Ordering::Relaxed);
+        daemon.join().unwrap();
+    }
+
+    #[test]
+    fn external_changes_follow_the_policy() {
+        let backend = Arc::new(MemoryBackend::default());
+        let dir = tempfile::tempdir().unwrap();
+        let config = MyApp {
+            auto_mode_change: true,
+            night_windows: vec![DarkWindow::new(0, 0)],
+            external_change_policy: ExternalChangePolicy::Reassert,
+            config_path: dir.path().join("config.toml"),
+            ..Default::default()
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread_backend = backend.clone();
+        let daemon = thread::spawn(move || run_until(config, thread_backend, None, &thread_stop));
+        assert!(wait_for(|| backend.is_dark_mode_enabled().unwrap()));
+
+        // 在系统设置中改为浅色后立即改回定时计划的深色
+        backend.set_dark_mode(false).unwrap();
+        assert!(wait_for(|| backend.is_dark_mode_enabled().unwrap()));
+
+        stop.store(true, Ordering::Relaxed);
+        daemon.join().unwrap();
+    }
+}
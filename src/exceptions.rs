@@ -0,0 +1,530 @@
+// 特殊日期(节假日、出差等)的安排，可以从本地的 .ics 日历文件导入
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExceptionKind {
+    // 全天浅色
+    #[default]
+    AlwaysLight,
+    // 全天深色
+    AlwaysDark,
+    // 使用备用时间段
+    Alternate,
+}
+
+impl ExceptionKind {
+    pub const ALL: [ExceptionKind; 3] = [
+        ExceptionKind::AlwaysLight,
+        ExceptionKind::AlwaysDark,
+        ExceptionKind::Alternate,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ExceptionKind::AlwaysLight => "全天浅色",
+            ExceptionKind::AlwaysDark => "全天深色",
+            ExceptionKind::Alternate => "使用备用时间段",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DateException {
+    #[serde(with = "date_format")]
+    pub date: NaiveDate,
+    pub kind: ExceptionKind,
+    // 日历中的事件名称
+    #[serde(default)]
+    pub summary: String,
+}
+
+mod date_format {
+    use chrono::NaiveDate;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const FORMAT: &str = "%Y-%m-%d";
+
+    pub fn serialize<S: Serializer>(date: &NaiveDate, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&date.format(FORMAT).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveDate, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        NaiveDate::parse_from_str(&text, FORMAT).map_err(serde::de::Error::custom)
+    }
+}
+
+// 导入的日期，以及导入时需要提醒的问题
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Imported {
+    pub exceptions: Vec<DateException>,
+    pub warnings: Vec<String>,
+}
+
+pub fn import_ics(path: &Path, kind: ExceptionKind) -> Result<Imported, Box<dyn Error>> {
+    parse_ics(&fs::read_to_string(path)?, kind)
+}
+
+// 读取日历中每个事件覆盖的日期，多天的事件会展开成每一天，重复的事件展开成每一次
+pub fn parse_ics(text: &str, kind: ExceptionKind) -> Result<Imported, Box<dyn Error>> {
+    let mut imported = Imported::default();
+    let mut event: Option<Event> = None;
+    for (number, line) in unfold(text) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        // 属性名后面可能带有参数，例如 DTSTART;VALUE=DATE
+        let (name, params) = name.split_once(';').unwrap_or((name, ""));
+        let error = |message: &str| format!("第 {} 行 {}：{}", number, name, message);
+        match (name.to_ascii_uppercase().as_str(), event.as_mut()) {
+            ("BEGIN", _) if value.eq_ignore_ascii_case("VEVENT") => event = Some(Event::default()),
+            ("END", Some(current)) if value.eq_ignore_ascii_case("VEVENT") => {
+                let dates = current.dates().map_err(|message| error(&message))?;
+                imported
+                    .exceptions
+                    .extend(dates.into_iter().map(|date| DateException {
+                        date,
+                        kind,
+                        summary: current.summary.clone(),
+                    }));
+                if let Some((line, zone)) = &current.zone {
+                    imported.warnings.push(format!(
+                        "第 {} 行：事件「{}」的时间属于时区 {}，按日历中写的时间计算日期",
+                        line, current.summary, zone
+                    ));
+                }
+                event = None;
+            }
+            ("DTSTART", Some(current)) => {
+                let start = parse_date_time(value, params).ok_or_else(|| error(value))?;
+                current.note_zone(number, params, start);
+                current.start = Some(start);
+            }
+            ("DTEND", Some(current)) => {
+                let end = parse_date_time(value, params).ok_or_else(|| error(value))?;
+                current.note_zone(number, params, end);
+                current.end = Some(end);
+            }
+            ("RRULE", Some(current)) => current.rule = Some((number, value.trim().to_owned())),
+            ("EXDATE", Some(current)) => {
+                for value in value.split(',') {
+                    let (time, _) = parse_date_time(value, params).ok_or_else(|| error(value))?;
+                    current.excluded.push(time.date());
+                }
+            }
+            ("SUMMARY", Some(current)) => current.summary = unescape(value),
+            _ => {}
+        }
+    }
+    if event.is_some() {
+        return Err("日历文件在事件结束前中断".into());
+    }
+    Ok(imported)
+}
+
+// 导入的日期覆盖已有的同一天，结果按日期排序
+pub fn merge(exceptions: &mut Vec<DateException>, imported: Vec<DateException>) {
+    for exception in imported {
+        exceptions.retain(|existing| existing.date != exception.date);
+        exceptions.push(exception);
+    }
+    exceptions.sort_by_key(|exception| exception.date);
+}
+
+#[derive(Debug, Default)]
+struct Event {
+    start: Option<(NaiveDateTime, bool)>,
+    end: Option<(NaiveDateTime, bool)>,
+    summary: String,
+    // RRULE 所在的行和规则
+    rule: Option<(usize, String)>,
+    // EXDATE 排除的日期
+    excluded: Vec<NaiveDate>,
+    // 带时间的 DTSTART/DTEND 使用的时区，没有换算
+    zone: Option<(usize, String)>,
+}
+
+impl Event {
+    fn note_zone(&mut self, line: usize, params: &str, (_, date_only): (NaiveDateTime, bool)) {
+        let zone = params
+            .split(';')
+            .find_map(|param| param.strip_prefix("TZID="));
+        if let Some(zone) = zone.filter(|_| !date_only && self.zone.is_none()) {
+            self.zone = Some((line, zone.to_owned()));
+        }
+    }
+
+    // 事件覆盖的日期。只有日期的 DTEND 不包含在内；带时间的包含结束时间所在的那天，
+    // 除非正好在午夜结束
+    fn dates(&self) -> Result<Vec<NaiveDate>, String> {
+        let (start, _) = self.start.ok_or("事件没有 DTSTART")?;
+        let last = match self.end {
+            Some((end, true)) => end.date() - Duration::days(1),
+            Some((end, false)) if end.time() == chrono::NaiveTime::MIN => {
+                end.date() - Duration::days(1)
+            }
+            Some((end, false)) => end.date(),
+            None => start.date(),
+        };
+        let days = (last - start.date()).num_days().max(0);
+        let starts = match &self.rule {
+            None => vec![start.date()],
+            Some((line, rule)) => Rule::parse(rule, start.date())
+                .map_err(|message| {
+                    format!(
+                        "第 {} 行 RRULE：事件「{}」的重复规则 {} {}",
+                        line, self.summary, rule, message
+                    )
+                })?
+                .occurrences(start.date()),
+        };
+        Ok(starts
+            .into_iter()
+            .filter(|date| !self.excluded.contains(date))
+            .flat_map(|first| (0..=days).map(move |day| first + Duration::days(day)))
+            .collect())
+    }
+}
+
+// 没有 COUNT 和 UNTIL 的重复事件只展开这么多年
+const MAX_YEARS: i32 = 10;
+
+// 支持的重复规则：每年或每天重复，可以带 INTERVAL、COUNT、UNTIL
+#[derive(Debug, PartialEq, Eq)]
+struct Rule {
+    yearly: bool,
+    interval: u32,
+    count: Option<u32>,
+    until: Option<NaiveDate>,
+}
+
+impl Rule {
+    fn parse(text: &str, start: NaiveDate) -> Result<Rule, String> {
+        let mut rule = Rule {
+            yearly: false,
+            interval: 1,
+            count: None,
+            until: None,
+        };
+        let mut frequency = None;
+        for part in text.split(';').filter(|part| !part.is_empty()) {
+            let (name, value) = part.split_once('=').unwrap_or((part, ""));
+            let number = || value.parse::<u32>().ok().filter(|&n| n > 0);
+            let unsupported = || format!("中的 {} 不支持", part);
+            match name.to_ascii_uppercase().as_str() {
+                "FREQ" => frequency = Some(value.to_ascii_uppercase()),
+                "INTERVAL" => rule.interval = number().ok_or_else(unsupported)?,
+                "COUNT" => rule.count = Some(number().ok_or_else(unsupported)?),
+                "UNTIL" => {
+                    let date = value.get(..8).unwrap_or(value);
+                    rule.until =
+                        Some(NaiveDate::parse_from_str(date, "%Y%m%d").map_err(|_| unsupported())?);
+                }
+                // 每年重复的节假日常常重复写出开始的月和日
+                "BYMONTH" if value.parse() == Ok(start.month()) => {}
+                "BYMONTHDAY" if value.parse() == Ok(start.day()) => {}
+                "WKST" => {}
+                _ => return Err(unsupported()),
+            }
+        }
+        match frequency.as_deref() {
+            Some("YEARLY") => rule.yearly = true,
+            Some("DAILY") => {}
+            _ => return Err("不支持，只能导入每年或每天重复的事件".to_owned()),
+        }
+        Ok(rule)
+    }
+
+    // 每次重复的开始日期。2 月 29 日开始的每年重复只在闰年出现
+    fn occurrences(&self, start: NaiveDate) -> Vec<NaiveDate> {
+        let last_year = i64::from(start.year()) + i64::from(MAX_YEARS);
+        let mut dates = Vec::new();
+        for n in 0.. {
+            let step = n * i64::from(self.interval);
+            let date = if self.yearly {
+                let year = i64::from(start.year()) + step;
+                if year >= last_year {
+                    break;
+                }
+                match i32::try_from(year)
+                    .ok()
+                    .and_then(|year| start.with_year(year))
+                {
+                    Some(date) => date,
+                    None => continue,
+                }
+            } else {
+                match start.checked_add_signed(Duration::days(step)) {
+                    Some(date) if i64::from(date.year()) < last_year => date,
+                    _ => break,
+                }
+            };
+            if self.until.is_some_and(|until| date > until) {
+                break;
+            }
+            dates.push(date);
+            if self
+                .count
+                .is_some_and(|count| dates.len() >= count as usize)
+            {
+                break;
+            }
+        }
+        dates
+    }
+}
+
+// 长行会被折成以空格或制表符开头的多行，合并后带上原来的行号
+fn unfold(text: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some((_, last))) => last.push_str(rest),
+            _ => lines.push((number + 1, line.to_owned())),
+        }
+    }
+    lines
+}
+
+// 返回时间和是否只有日期。TZID 指定的时区没有换算，按日历中写的时间计算
+fn parse_date_time(value: &str, params: &str) -> Option<(NaiveDateTime, bool)> {
+    let value = value.trim();
+    let date_only = params
+        .split(';')
+        .any(|param| param.eq_ignore_ascii_case("VALUE=DATE"))
+        || value.len() == 8;
+    if date_only {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").ok()?;
+        return Some((date.and_time(chrono::NaiveTime::MIN), true));
+    }
+    // 以 Z 结尾的是 UTC 时间，换算成本地时间
+    if let Some(utc) = value.strip_suffix(['Z', 'z']) {
+        let time = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+        return Some((
+            Utc.from_utc_datetime(&time)
+                .with_timezone(&Local)
+                .naive_local(),
+            false,
+        ));
+    }
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .ok()
+        .map(|time| (time, false))
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') | Some('N') => result.push(' '),
+            Some(other) => result.push(other),
+            None => {}
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, month, day).unwrap()
+    }
+
+    const HOLIDAYS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//Example//Holidays//CN\r
+BEGIN:VEVENT\r
+UID:national-day@example.com\r
+DTSTART;VALUE=DATE:20241001\r
+DTEND;VALUE=DATE:20241004\r
+SUMMARY:国庆节\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:trip@example.com\r
+DTSTART;TZID=Asia/Shanghai:20240610T090000\r
+DTEND;TZID=Asia/Shanghai:20240611T180000\r
+SUMMARY:出差\\, 北京\r
+ 到上海\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:single@example.com\r
+DTSTART:20240501T120000Z\r
+SUMMARY:劳动节\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    #[test]
+    fn parses_all_day_timed_and_single_events() {
+        let imported = parse_ics(HOLIDAYS, ExceptionKind::AlwaysLight).unwrap();
+        let exceptions = imported.exceptions;
+        let dates: Vec<NaiveDate> = exceptions.iter().map(|e| e.date).collect();
+        // 全天事件的 DTEND 不包含在内
+        assert_eq!(
+            dates,
+            [
+                date(10, 1),
+                date(10, 2),
+                date(10, 3),
+                date(6, 10),
+                date(6, 11),
+                date(5, 1)
+            ]
+        );
+        assert_eq!(exceptions[0].summary, "国庆节");
+        // 转义的逗号和折行
+        assert_eq!(exceptions[3].summary, "出差, 北京到上海");
+        assert!(exceptions
+            .iter()
+            .all(|e| e.kind == ExceptionKind::AlwaysLight));
+        // 带时区的时间没有换算，提醒一下
+        assert_eq!(
+            imported.warnings,
+            ["第 12 行：事件「出差, 北京到上海」的时间属于时区 Asia/Shanghai，按日历中写的时间计算日期"]
+        );
+    }
+
+    fn dates(ics: &str) -> Vec<NaiveDate> {
+        let imported = parse_ics(ics, ExceptionKind::AlwaysLight).unwrap();
+        imported.exceptions.iter().map(|e| e.date).collect()
+    }
+
+    #[test]
+    fn recurring_events_are_expanded() {
+        let ymd = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+        let yearly = "BEGIN:VEVENT\nDTSTART;VALUE=DATE:20241001\nDTEND;VALUE=DATE:20241003\nRRULE:FREQ=YEARLY;BYMONTH=10;BYMONTHDAY=1;COUNT=3\nSUMMARY:国庆节\nEND:VEVENT\n";
+        assert_eq!(
+            dates(yearly),
+            [
+                ymd(2024, 10, 1),
+                ymd(2024, 10, 2),
+                ymd(2025, 10, 1),
+                ymd(2025, 10, 2),
+                ymd(2026, 10, 1),
+                ymd(2026, 10, 2)
+            ]
+        );
+        let until = "BEGIN:VEVENT\nDTSTART;VALUE=DATE:20240101\nRRULE:FREQ=YEARLY;INTERVAL=2;UNTIL=20280101T000000Z\nEND:VEVENT\n";
+        assert_eq!(
+            dates(until),
+            [ymd(2024, 1, 1), ymd(2026, 1, 1), ymd(2028, 1, 1)]
+        );
+        let daily = "BEGIN:VEVENT\nDTSTART:20240610T090000\nRRULE:FREQ=DAILY;COUNT=4\nEXDATE:20240611T090000,20240612T090000\nEND:VEVENT\n";
+        assert_eq!(dates(daily), [ymd(2024, 6, 10), ymd(2024, 6, 13)]);
+        // 没有结束的规则只展开十年，闰日只在闰年出现
+        let forever = "BEGIN:VEVENT\nDTSTART;VALUE=DATE:20240229\nRRULE:FREQ=YEARLY\nEND:VEVENT\n";
+        assert_eq!(
+            dates(forever),
+            [ymd(2024, 2, 29), ymd(2028, 2, 29), ymd(2032, 2, 29)]
+        );
+        let forever = "BEGIN:VEVENT\nDTSTART;VALUE=DATE:20240501\nRRULE:FREQ=YEARLY\nEND:VEVENT\n";
+        assert_eq!(dates(forever).len(), 10);
+    }
+
+    #[test]
+    fn unsupported_rules_name_the_event() {
+        let weekly = "BEGIN:VEVENT\nDTSTART;VALUE=DATE:20240101\nRRULE:FREQ=WEEKLY;COUNT=3\nSUMMARY:例会\nEND:VEVENT\n";
+        assert_eq!(
+            parse_ics(weekly, ExceptionKind::AlwaysDark)
+                .unwrap_err()
+                .to_string(),
+            "第 5 行 END：第 3 行 RRULE：事件「例会」的重复规则 FREQ=WEEKLY;COUNT=3 不支持，只能导入每年或每天重复的事件"
+        );
+        let by_day = "BEGIN:VEVENT\nDTSTART;VALUE=DATE:20241128\nRRULE:FREQ=YEARLY;BYMONTH=11;BYDAY=4TH\nSUMMARY:感恩节\nEND:VEVENT\n";
+        let error = parse_ics(by_day, ExceptionKind::AlwaysDark).unwrap_err();
+        assert!(
+            error.to_string().ends_with(
+                "事件「感恩节」的重复规则 FREQ=YEARLY;BYMONTH=11;BYDAY=4TH 中的 BYDAY=4TH 不支持"
+            ),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn utc_times_are_converted_to_local_dates() {
+        let time = NaiveDate::from_ymd_opt(2024, 3, 1)
+            .unwrap()
+            .and_hms_opt(23, 30, 0)
+            .unwrap();
+        let local = Utc
+            .from_utc_datetime(&time)
+            .with_timezone(&Local)
+            .date_naive();
+        assert_eq!(
+            dates("BEGIN:VEVENT\nDTSTART:20240301T233000Z\nEND:VEVENT\n"),
+            [local]
+        );
+    }
+
+    #[test]
+    fn timed_event_ending_at_midnight_does_not_cover_the_next_day() {
+        let ics = "BEGIN:VEVENT\nDTSTART:20240301T200000\nDTEND:20240302T000000\nEND:VEVENT\n";
+        let exceptions = parse_ics(ics, ExceptionKind::AlwaysDark)
+            .unwrap()
+            .exceptions;
+        assert_eq!(exceptions.len(), 1);
+        assert_eq!(exceptions[0].date, date(3, 1));
+    }
+
+    #[test]
+    fn reports_broken_files() {
+        let missing_start = "BEGIN:VEVENT\nSUMMARY:x\nEND:VEVENT\n";
+        let error = parse_ics(missing_start, ExceptionKind::AlwaysDark).unwrap_err();
+        assert!(error.to_string().contains("DTSTART"), "{}", error);
+        let bad_date = "BEGIN:VEVENT\nDTSTART;VALUE=DATE:2024-13-01\nEND:VEVENT\n";
+        let error = parse_ics(bad_date, ExceptionKind::AlwaysDark).unwrap_err();
+        assert!(error.to_string().contains("2024-13-01"), "{}", error);
+        assert!(parse_ics(
+            "BEGIN:VEVENT\nDTSTART:20240101\n",
+            ExceptionKind::AlwaysDark
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn import_replaces_the_same_date() {
+        let mut exceptions = parse_ics(HOLIDAYS, ExceptionKind::AlwaysLight)
+            .unwrap()
+            .exceptions;
+        let trip = "BEGIN:VEVENT\nDTSTART;VALUE=DATE:20241002\nSUMMARY:夜班\nEND:VEVENT\n";
+        merge(
+            &mut exceptions,
+            parse_ics(trip, ExceptionKind::Alternate)
+                .unwrap()
+                .exceptions,
+        );
+        assert_eq!(exceptions.len(), 6);
+        assert_eq!(exceptions[0].date, date(5, 1));
+        let replaced = exceptions.iter().find(|e| e.date == date(10, 2)).unwrap();
+        assert_eq!(replaced.kind, ExceptionKind::Alternate);
+        assert_eq!(replaced.summary, "夜班");
+    }
+
+    #[test]
+    fn reads_files_from_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("holidays.ics");
+        fs::write(&path, HOLIDAYS).unwrap();
+        assert_eq!(
+            import_ics(&path, ExceptionKind::AlwaysDark)
+                .unwrap()
+                .exceptions
+                .len(),
+            6
+        );
+        assert!(import_ics(&dir.path().join("missing.ics"), ExceptionKind::AlwaysDark).is_err());
+    }
+}
@@ -0,0 +1,175 @@
+// 离线估计经纬度：根据系统时区查 tzdb 的时区表，或者在内置城市列表中搜索
+use crate::solar::Location;
+
+const ZONE1970_TAB: &str = include_str!("../resources/zoneinfo/zone1970.tab");
+// 旧版时区表，还包含 Europe/Oslo 这样在 zone1970.tab 中已经合并的时区
+const ZONE_TAB: &str = include_str!("../resources/zoneinfo/zone.tab");
+const CITIES: &str = include_str!("../resources/cities.tsv");
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct City {
+    pub name: &'static str,
+    pub local_name: &'static str,
+    pub location: Location,
+}
+
+// 根据系统时区估计位置，返回时区名和该时区代表城市的经纬度
+pub fn guess_location() -> Option<(String, Location)> {
+    let timezone = iana_time_zone::get_timezone()
+        .map_err(|e| eprintln!("Failed to get system timezone: {}", e))
+        .ok()?;
+    let location = timezone_location(&timezone)?;
+    Some((timezone, location))
+}
+
+// 时区(例如 Asia/Shanghai)代表城市的经纬度
+pub fn timezone_location(timezone: &str) -> Option<Location> {
+    [ZONE1970_TAB, ZONE_TAB]
+        .into_iter()
+        .find_map(|table| lookup_zone(table, timezone))
+}
+
+fn lookup_zone(table: &str, timezone: &str) -> Option<Location> {
+    table
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .find_map(|line| {
+            let mut columns = line.split('\t');
+            let coordinates = columns.nth(1)?;
+            if columns.next()? != timezone {
+                return None;
+            }
+            parse_iso6709(coordinates)
+        })
+}
+
+// 解析 ±DDMM±DDDMM 或 ±DDMMSS±DDDMMSS 格式的坐标
+fn parse_iso6709(text: &str) -> Option<Location> {
+    let split = text.get(1..)?.find(['+', '-'])? + 1;
+    let (latitude, longitude) = text.split_at(split);
+    Some(Location {
+        latitude: parse_degrees(latitude, 2)?,
+        longitude: parse_degrees(longitude, 3)?,
+    })
+}
+
+fn parse_degrees(text: &str, degree_digits: usize) -> Option<f64> {
+    let sign = match text.chars().next()? {
+        '+' => 1.0,
+        '-' => -1.0,
+        _ => return None,
+    };
+    let digits = &text[1..];
+    if !digits.bytes().all(|b| b.is_ascii_digit())
+        || (digits.len() != degree_digits + 2 && digits.len() != degree_digits + 4)
+    {
+        return None;
+    }
+    let part = |range: std::ops::Range<usize>| digits.get(range)?.parse::<f64>().ok();
+    let degrees = part(0..degree_digits)?;
+    let minutes = part(degree_digits..degree_digits + 2)?;
+    // 秒可以省略
+    let seconds = part(degree_digits + 2..degree_digits + 4).unwrap_or(0.0);
+    Some(sign * (degrees + minutes / 60.0 + seconds / 3600.0))
+}
+
+// 内置城市列表，格式见 resources/cities.tsv
+pub fn cities() -> impl Iterator<Item = City> {
+    CITIES
+        .lines()
+        .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+        .filter_map(|line| {
+            let mut columns = line.split('\t');
+            Some(City {
+                name: columns.next()?,
+                local_name: columns.next()?,
+                location: Location {
+                    latitude: columns.next()?.parse().ok()?,
+                    longitude: columns.next()?.parse().ok()?,
+                },
+            })
+        })
+}
+
+// 按中文名或英文名(不区分大小写)搜索城市
+pub fn search_cities(query: &str) -> Vec<City> {
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return Vec::new();
+    }
+    cities()
+        .filter(|city| {
+            city.name.to_lowercase().contains(&query) || city.local_name.contains(&query)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(location: Location, latitude: f64, longitude: f64) {
+        assert!(
+            (location.latitude - latitude).abs() < 0.01
+                && (location.longitude - longitude).abs() < 0.01,
+            "{:?}",
+            location
+        );
+    }
+
+    #[test]
+    fn parses_both_coordinate_precisions() {
+        assert_near(parse_iso6709("+3114+12128").unwrap(), 31.2333, 121.4667);
+        // 纽约：+404251-0740023
+        assert_near(parse_iso6709("+404251-0740023").unwrap(), 40.7142, -74.0064);
+        assert_near(parse_iso6709("-3352+15113").unwrap(), -33.8667, 151.2167);
+        assert_eq!(parse_iso6709("+3114"), None);
+        assert_eq!(parse_iso6709("+31x4+12128"), None);
+        assert_eq!(parse_iso6709(""), None);
+    }
+
+    #[test]
+    fn looks_up_timezones_in_bundled_tables() {
+        assert_near(
+            timezone_location("Asia/Shanghai").unwrap(),
+            31.2333,
+            121.4667,
+        );
+        assert_near(
+            timezone_location("Europe/London").unwrap(),
+            51.5083,
+            -0.1253,
+        );
+        // 只在旧版时区表中出现
+        assert!(timezone_location("Europe/Oslo").is_some());
+        assert_eq!(timezone_location("Mars/Olympus_Mons"), None);
+    }
+
+    #[test]
+    fn every_bundled_row_parses() {
+        for table in [ZONE1970_TAB, ZONE_TAB] {
+            for line in table.lines().filter(|line| !line.starts_with('#')) {
+                let timezone = line.split('\t').nth(2).unwrap();
+                assert!(lookup_zone(table, timezone).is_some(), "{}", line);
+            }
+        }
+        assert!(cities().count() > 50);
+        assert_eq!(
+            cities().count(),
+            CITIES
+                .lines()
+                .filter(|line| !line.starts_with('#') && !line.trim().is_empty())
+                .count()
+        );
+    }
+
+    #[test]
+    fn searches_cities_by_either_name() {
+        let found = search_cities("哈尔滨");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "Harbin");
+        assert_near(search_cities("TROMS")[0].location, 69.6492, 18.9553);
+        assert!(search_cities("  ").is_empty());
+        assert!(search_cities("atlantis").is_empty());
+    }
+}
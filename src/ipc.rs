@@ -0,0 +1,630 @@
+// 本机控制接口：正在运行的实例在 Unix 套接字(Windows 下是命名管道)上监听，
+// 每行一个 JSON 请求，每个请求回复一行 JSON
+use crate::cli::{Status, Target};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// 客户端等待回复的时间，实例卡住时不会一直等下去
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+// 连接线程等待处理结果的时间，比客户端短，这样客户端能收到具体的错误
+const HANDLE_TIMEOUT: Duration = Duration::from_secs(4);
+const NOT_RESPONDING: &str = "正在运行的实例没有响应";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    Status,
+    Set { dark: bool, target: Target },
+    // 暂停和恢复定时切换
+    Pause,
+    Resume,
+    // 重新读取配置文件
+    Reload,
+    // 显示设置窗口，再次启动程序时发送
+    Show,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+}
+
+impl Response {
+    pub fn ok() -> Self {
+        Self {
+            ok: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            error: Some(message.into()),
+            ..Default::default()
+        }
+    }
+
+    pub fn status(status: Status) -> Self {
+        Self {
+            status: Some(status),
+            ..Self::ok()
+        }
+    }
+}
+
+// 监听的地址，可以用 AUTODARK_SOCKET 环境变量指定
+pub fn endpoint() -> PathBuf {
+    if let Some(path) = env::var_os("AUTODARK_SOCKET") {
+        return path.into();
+    }
+    transport::default_endpoint()
+}
+
+// 收到的一个请求，处理后用 reply 回复
+#[derive(Debug)]
+pub struct Incoming {
+    pub request: Request,
+    reply: mpsc::Sender<Response>,
+}
+
+impl Incoming {
+    pub fn reply(self, response: Response) {
+        // 客户端已经断开时不需要回复
+        let _ = self.reply.send(response);
+    }
+}
+
+// 收到请求时调用，例如用来唤醒界面
+type Waker = Arc<Mutex<Option<Box<dyn Fn() + Send>>>>;
+
+// 请求在连接线程中读取，交给持有设置的一方(界面或后台模式)处理
+pub struct Server {
+    path: PathBuf,
+    requests: mpsc::Receiver<Incoming>,
+    waker: Waker,
+    stop: Arc<AtomicBool>,
+}
+
+// 启动时先尝试监听，监听成功的是唯一的实例，否则连接到已经在运行的实例
+pub enum Instance {
+    First(Server),
+    Running(Client),
+}
+
+pub fn claim(path: PathBuf) -> io::Result<Instance> {
+    match Server::bind(path.clone()) {
+        Ok(server) => Ok(Instance::First(server)),
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            Client::connect(&path).map(Instance::Running)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+impl Server {
+    // 开始监听，已经有实例在监听时返回 AddrInUse
+    pub fn bind(path: PathBuf) -> io::Result<Self> {
+        let listener = transport::bind(&path)?;
+        let (sender, requests) = mpsc::channel();
+        let waker = Waker::default();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (thread_waker, thread_stop) = (waker.clone(), stop.clone());
+        thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok(stream) => {
+                        let sender = sender.clone();
+                        let waker = thread_waker.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = serve(stream, &sender, &waker) {
+                                log::debug!("Control connection closed: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => log::warn!("Failed to accept control connection: {}", e),
+                }
+            }
+        });
+        Ok(Self {
+            path,
+            requests,
+            waker,
+            stop,
+        })
+    }
+
+    // 每收到一个请求调用 waker
+    pub fn set_waker(&self, waker: impl Fn() + Send + 'static) {
+        *self.waker.lock().unwrap() = Some(Box::new(waker));
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn try_recv(&self) -> Option<Incoming> {
+        self.requests.try_recv().ok()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Incoming> {
+        self.requests.recv_timeout(timeout).ok()
+    }
+}
+
+impl fmt::Debug for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Server").field("path", &self.path).finish()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        transport::close(&self.path);
+    }
+}
+
+// 处理一个连接上的所有请求
+fn serve(
+    stream: transport::Stream,
+    sender: &mpsc::Sender<Incoming>,
+    waker: &Waker,
+) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str(&line) {
+            Ok(request) => {
+                let (reply, response) = mpsc::channel();
+                if sender.send(Incoming { request, reply }).is_err() {
+                    return Ok(());
+                }
+                if let Some(waker) = waker.lock().unwrap().as_ref() {
+                    waker();
+                }
+                match response.recv_timeout(HANDLE_TIMEOUT) {
+                    Ok(response) => response,
+                    Err(mpsc::RecvTimeoutError::Timeout) => Response::error(NOT_RESPONDING),
+                    Err(mpsc::RecvTimeoutError::Disconnected) => Response::error("实例正在退出"),
+                }
+            }
+            Err(e) => Response::error(format!("无效的请求：{}", e)),
+        };
+        writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+// 读写超时转换为容易看懂的错误
+fn not_responding(error: io::Error) -> Box<dyn Error> {
+    match error.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => NOT_RESPONDING.into(),
+        _ => error.into(),
+    }
+}
+
+pub struct Client {
+    reader: BufReader<transport::Stream>,
+    writer: transport::Stream,
+    timeout: Duration,
+}
+
+impl Client {
+    // 没有实例在监听时返回错误
+    pub fn connect(path: &Path) -> io::Result<Self> {
+        let writer = transport::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Self {
+            reader,
+            writer,
+            timeout: TIMEOUT,
+        })
+    }
+
+    // 发送请求，实例返回错误时转换为 Err。超过 TIMEOUT 没有回复时返回错误
+    pub fn request(&mut self, request: &Request) -> Result<Response, Box<dyn Error>> {
+        transport::set_timeout(&self.writer, self.timeout)?;
+        writeln!(self.writer, "{}", serde_json::to_string(request)?).map_err(not_responding)?;
+        self.writer.flush().map_err(not_responding)?;
+        if self.reader.buffer().is_empty() {
+            transport::wait_readable(self.reader.get_ref(), self.timeout)
+                .map_err(not_responding)?;
+        }
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).map_err(not_responding)? == 0 {
+            return Err("实例没有回复就断开了连接".into());
+        }
+        let response: Response = serde_json::from_str(&line)?;
+        match response.error {
+            Some(error) if !response.ok => Err(error.into()),
+            _ => Ok(response),
+        }
+    }
+
+    pub fn status(&mut self) -> Result<Status, Box<dyn Error>> {
+        self.request(&Request::Status)?
+            .status
+            .ok_or_else(|| "实例的回复中缺少状态".into())
+    }
+}
+
+#[cfg(unix)]
+mod transport {
+    use std::env;
+    use std::fs::{self, DirBuilder, File, OpenOptions};
+    use std::io;
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    pub type Stream = UnixStream;
+
+    pub struct Listener(UnixListener);
+
+    impl Listener {
+        pub fn accept(&self) -> io::Result<Stream> {
+            self.0.accept().map(|(stream, _)| stream)
+        }
+    }
+
+    fn uid() -> u32 {
+        unsafe { libc::geteuid() }
+    }
+
+    pub fn default_endpoint() -> PathBuf {
+        match env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) => Path::new(&dir).join("autodark.sock"),
+            // 公共的临时目录中放在只有自己能访问的子目录里，其他用户不能抢先创建套接字
+            None => env::temp_dir()
+                .join(format!("autodark-{}", uid()))
+                .join("autodark.sock"),
+        }
+    }
+
+    fn dir_of(path: &Path) -> &Path {
+        match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        }
+    }
+
+    // 套接字所在的目录必须属于当前用户，并且其他用户不能写入，否则别人可以冒充实例
+    fn check_dir(dir: &Path) -> io::Result<()> {
+        let metadata = fs::symlink_metadata(dir)?;
+        if !metadata.is_dir() || metadata.uid() != uid() || metadata.mode() & 0o022 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} 不属于当前用户，或者其他用户也可以写入", dir.display()),
+            ));
+        }
+        Ok(())
+    }
+
+    // 检查、删除和创建套接字期间持有的锁，两个实例同时启动或退出时不会删掉对方的套接字。
+    // 进程退出时自动释放
+    fn lock(path: &Path) -> io::Result<File> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .mode(0o600)
+            .open(path.with_extension("lock"))?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(file)
+    }
+
+    pub fn bind(path: &Path) -> io::Result<Listener> {
+        let dir = dir_of(path);
+        // 第一次使用时创建只有自己能访问的目录
+        if let Err(e) = DirBuilder::new().mode(0o700).create(dir) {
+            if e.kind() != io::ErrorKind::AlreadyExists {
+                return Err(e);
+            }
+        }
+        check_dir(dir)?;
+        let _lock = lock(path)?;
+        match UnixListener::bind(path) {
+            // 上次没有正常退出时留下的套接字文件，连不上说明已经没有实例在监听
+            Err(e)
+                if e.kind() == io::ErrorKind::AddrInUse && UnixStream::connect(path).is_err() =>
+            {
+                fs::remove_file(path)?;
+                UnixListener::bind(path)
+            }
+            result => result,
+        }
+        .map(Listener)
+    }
+
+    pub fn connect(path: &Path) -> io::Result<Stream> {
+        check_dir(dir_of(path))?;
+        UnixStream::connect(path)
+    }
+
+    // 读写超过 timeout 时返回 WouldBlock
+    pub fn set_timeout(stream: &Stream, timeout: Duration) -> io::Result<()> {
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))
+    }
+
+    // 套接字的读取本身有超时
+    pub fn wait_readable(_stream: &Stream, _timeout: Duration) -> io::Result<()> {
+        Ok(())
+    }
+
+    // 停止监听：连接一次唤醒等待连接的线程，然后删除套接字文件
+    pub fn close(path: &Path) {
+        let _lock = lock(path);
+        let _ = UnixStream::connect(path);
+        let _ = fs::remove_file(path);
+    }
+}
+
+#[cfg(windows)]
+mod transport {
+    use std::env;
+    use std::fs::{File, OpenOptions};
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::io::{AsRawHandle, FromRawHandle};
+    use std::path::{Path, PathBuf};
+    use std::ptr;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use windows_sys::Win32::Foundation::{ERROR_PIPE_CONNECTED, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::Storage::FileSystem::{
+        FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX,
+    };
+    use windows_sys::Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, PeekNamedPipe, PIPE_READMODE_BYTE,
+        PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    pub type Stream = File;
+
+    // 命名管道的每个实例只能连接一个客户端，所以总是预先创建好下一个实例
+    pub struct Listener {
+        name: Vec<u16>,
+        next: Mutex<File>,
+    }
+
+    impl Listener {
+        pub fn accept(&self) -> io::Result<Stream> {
+            let mut next = self.next.lock().unwrap();
+            let handle = next.as_raw_handle() as _;
+            // 客户端在创建实例和等待之间连上时返回 ERROR_PIPE_CONNECTED
+            if unsafe { ConnectNamedPipe(handle, ptr::null_mut()) } == 0 {
+                let error = io::Error::last_os_error();
+                if error.raw_os_error() != Some(ERROR_PIPE_CONNECTED as i32) {
+                    return Err(error);
+                }
+            }
+            let connected = std::mem::replace(&mut *next, create(&self.name, false)?);
+            Ok(connected)
+        }
+    }
+
+    pub fn default_endpoint() -> PathBuf {
+        let user = env::var("USERNAME").unwrap_or_default();
+        PathBuf::from(format!(r"\\.\pipe\autodark-{}", user))
+    }
+
+    fn create(name: &[u16], first: bool) -> io::Result<File> {
+        let mut open_mode = PIPE_ACCESS_DUPLEX;
+        if first {
+            // 已经有实例在使用这个名字时失败
+            open_mode |= FILE_FLAG_FIRST_PIPE_INSTANCE;
+        }
+        let handle = unsafe {
+            CreateNamedPipeW(
+                name.as_ptr(),
+                open_mode,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                PIPE_UNLIMITED_INSTANCES,
+                4096,
+                4096,
+                0,
+                ptr::null(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            let error = io::Error::last_os_error();
+            return Err(if first {
+                io::Error::new(io::ErrorKind::AddrInUse, error)
+            } else {
+                error
+            });
+        }
+        Ok(unsafe { File::from_raw_handle(handle as _) })
+    }
+
+    pub fn bind(path: &Path) -> io::Result<Listener> {
+        let name: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+        let first = create(&name, true)?;
+        Ok(Listener {
+            name,
+            next: Mutex::new(first),
+        })
+    }
+
+    pub fn connect(path: &Path) -> io::Result<Stream> {
+        OpenOptions::new().read(true).write(true).open(path)
+    }
+
+    // 同步的命名管道没有读写超时，读取前用 wait_readable 等待数据
+    pub fn set_timeout(_stream: &Stream, _timeout: Duration) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn wait_readable(stream: &Stream, timeout: Duration) -> io::Result<()> {
+        let start = Instant::now();
+        loop {
+            let mut available = 0;
+            let peeked = unsafe {
+                PeekNamedPipe(
+                    stream.as_raw_handle() as _,
+                    ptr::null_mut(),
+                    0,
+                    ptr::null_mut(),
+                    &mut available,
+                    ptr::null_mut(),
+                )
+            };
+            // 管道断开时交给读取报告
+            if peeked == 0 || available > 0 {
+                return Ok(());
+            }
+            if start.elapsed() >= timeout {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    // 连接一次唤醒等待连接的线程，命名管道在所有句柄关闭后自动消失
+    pub fn close(path: &Path) {
+        let _ = connect(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protocol_is_line_delimited_json() {
+        assert_eq!(
+            serde_json::to_string(&Request::Set {
+                dark: true,
+                target: Target::App
+            })
+            .unwrap(),
+            r#"{"command":"set","dark":true,"target":"app"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Request>(r#"{"command":"pause"}"#).unwrap(),
+            Request::Pause
+        );
+        assert_eq!(
+            serde_json::to_string(&Response::error("不行")).unwrap(),
+            r#"{"ok":false,"error":"不行"}"#
+        );
+    }
+
+    #[test]
+    fn requests_are_answered_by_the_owner() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = Server::bind(dir.path().join("autodark.sock")).unwrap();
+        let mut client = Client::connect(server.path()).unwrap();
+        let owner = thread::spawn(move || {
+            for expected in [Request::Pause, Request::Reload] {
+                let incoming = server.recv_timeout(Duration::from_secs(5)).unwrap();
+                assert_eq!(incoming.request, expected);
+                incoming.reply(match expected {
+                    Request::Pause => Response::ok(),
+                    _ => Response::error("配置文件有错误"),
+                });
+            }
+            server
+        });
+        assert!(client.request(&Request::Pause).unwrap().ok);
+        assert_eq!(
+            client.request(&Request::Reload).unwrap_err().to_string(),
+            "配置文件有错误"
+        );
+        let server = owner.join().unwrap();
+
+        // 无效的请求直接由连接线程回复
+        writeln!(client.writer, "{{\"command\":\"restart\"}}").unwrap();
+        let mut line = String::new();
+        client.reader.read_line(&mut line).unwrap();
+        let response: Response = serde_json::from_str(&line).unwrap();
+        assert!(!response.ok);
+        assert!(response.error.unwrap().starts_with("无效的请求"));
+
+        // 停止监听后客户端连不上，回退到直接修改
+        let path = server.path().to_owned();
+        drop(server);
+        assert!(Client::connect(&path).is_err());
+    }
+
+    #[test]
+    fn only_the_first_instance_listens() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("autodark.sock");
+        let Instance::First(server) = claim(path.clone()).unwrap() else {
+            panic!("no instance is running yet");
+        };
+        let Instance::Running(mut client) = claim(path.clone()).unwrap() else {
+            panic!("the first instance is still listening");
+        };
+        let owner = thread::spawn(move || {
+            let incoming = server.recv_timeout(Duration::from_secs(5)).unwrap();
+            assert_eq!(incoming.request, Request::Show);
+            incoming.reply(Response::ok());
+            server
+        });
+        assert!(client.request(&Request::Show).unwrap().ok);
+        // 第一个实例退出后可以再启动
+        drop(owner.join().unwrap());
+        assert!(matches!(claim(path).unwrap(), Instance::First(_)));
+    }
+
+    #[test]
+    fn stuck_instances_time_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let server = Server::bind(dir.path().join("autodark.sock")).unwrap();
+        let mut client = Client::connect(server.path()).unwrap();
+        client.timeout = Duration::from_millis(100);
+        // 收到了请求但一直不回复
+        assert_eq!(
+            client.request(&Request::Status).unwrap_err().to_string(),
+            "正在运行的实例没有响应"
+        );
+        assert!(server.try_recv().is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sockets_live_in_a_private_directory() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        // 第一次使用时创建只有自己能访问的目录
+        let path = dir.path().join("autodark-1000").join("autodark.sock");
+        drop(Server::bind(path.clone()).unwrap());
+        let mode = fs::metadata(path.parent().unwrap())
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        // 其他用户可以写入的目录中不监听也不连接
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o777)).unwrap();
+        let shared = dir.path().join("autodark.sock");
+        let error = Server::bind(shared.clone()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(Client::connect(&shared).is_err());
+    }
+}
@@ -1,83 +1,280 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
-use chrono::{Local, NaiveTime};
+use chrono::{Local, NaiveTime, Timelike, Weekday};
 use eframe::egui;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::cmp::Ordering;
+use serde::{Deserialize, Serialize};
+use std::env;
 use std::error::Error;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::{env, fmt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
-// 在文件开头添加引用
+mod autostart;
+mod backend;
+mod cli;
+mod config;
+mod daemon;
+mod exceptions;
+mod geo;
+mod ipc;
+mod manual;
+mod reconcile;
+mod registry;
+mod scheduler;
+mod season;
+mod solar;
+mod time_expr;
+mod window;
 
-use winreg::enums::KEY_SET_VALUE;
-use winreg::{
-    enums::{HKEY_CURRENT_USER, KEY_READ},
-    RegKey,
-};
+use autostart::{remove_startup_entry, set_autostart};
+use backend::{BackendSettings, ThemeBackend};
+use cli::Target;
+use config::Config;
+use exceptions::{DateException, ExceptionKind};
+use ipc::{Instance, Request, Response, Server};
+use manual::{ExternalChangePolicy, Override, OverrideMode, OverrideSettings};
+use reconcile::{Reconciler, ThemeState};
+use scheduler::{Plan, Schedule, Scheduler};
+use season::{DateRange, MonthDay};
+use solar::{Location, PolarPolicy, SolarEvent, SolarSettings};
+use time_expr::TimeExpr;
+use window::{overlapping_pairs, TimeWindow, WeekWindows, WindowRule, WindowSet};
 
-#[derive(Default)]
-struct NaiveTimeWrapper(NaiveTime);
-impl PartialEq for NaiveTimeWrapper {
-    fn eq(&self, other: &Self) -> bool {
-        self.0 == other.0
-    }
+// 配置内容没有变化时不会写文件，返回是否写入
+fn save_config_to_toml(
+    config: &MyApp,
+    filename: &Path,
+    reconciler: &mut Reconciler,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let toml_string = toml::to_string_pretty(&Config::from(config))?;
+    reconciler.persist(&toml_string, filename)
 }
-
-impl Eq for NaiveTimeWrapper {}
-
-impl PartialOrd for NaiveTimeWrapper {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.0.cmp(&other.0))
-    }
+fn load_config_from_toml(filename: &Path) -> Result<MyApp, Box<dyn std::error::Error>> {
+    Ok(config::load(filename)?.into())
 }
-
-impl Ord for NaiveTimeWrapper {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.0.cmp(&other.0)
+// 配置中的一个深色时间段，开始和结束可以是时间表达式，例如 max(sunset - 30m, 18:00)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct DarkWindow {
+    start: TimeExpr,
+    end: TimeExpr,
+}
+impl DarkWindow {
+    fn new(start_hh: u32, end_hh: u32) -> Self {
+        Self {
+            start: NaiveTime::from_hms_opt(start_hh, 0, 0).unwrap().into(),
+            end: NaiveTime::from_hms_opt(end_hh, 0, 0).unwrap().into(),
+        }
+    }
+    fn rule(&self) -> WindowRule {
+        WindowRule::new(self.start.clone(), self.end.clone())
     }
 }
-impl fmt::Display for NaiveTimeWrapper {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0.format("%H:%M:%S"))
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+fn weekday_label(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "周一",
+        Weekday::Tue => "周二",
+        Weekday::Wed => "周三",
+        Weekday::Thu => "周四",
+        Weekday::Fri => "周五",
+        Weekday::Sat => "周六",
+        Weekday::Sun => "周日",
     }
 }
-impl Serialize for NaiveTimeWrapper {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        serializer.serialize_str(&self.0.format("%H:%M:%S").to_string())
+// 按星期区分深色时间段的方式
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum WeekdayMode {
+    #[default]
+    SameEveryDay,
+    WorkdaysWeekends,
+    PerDay,
+}
+impl WeekdayMode {
+    const ALL: [WeekdayMode; 3] = [
+        WeekdayMode::SameEveryDay,
+        WeekdayMode::WorkdaysWeekends,
+        WeekdayMode::PerDay,
+    ];
+    fn label(&self) -> &'static str {
+        match self {
+            WeekdayMode::SameEveryDay => "每天相同",
+            WeekdayMode::WorkdaysWeekends => "工作日和周末分开设置",
+            WeekdayMode::PerDay => "每天分别设置",
+        }
     }
 }
-impl fmt::Debug for NaiveTimeWrapper {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self.0)
+// 按星期设置的深色时间段，跨午夜的时间段属于开始的那一天
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct WeekdaySettings {
+    mode: WeekdayMode,
+    // 周六、周日的时间段，工作日使用 night_windows
+    weekend: Vec<DarkWindow>,
+    // 特殊日期设为使用备用时间段时的时间段
+    alternate: Vec<DarkWindow>,
+    // 每天分别设置时各天的时间段
+    monday: Vec<DarkWindow>,
+    tuesday: Vec<DarkWindow>,
+    wednesday: Vec<DarkWindow>,
+    thursday: Vec<DarkWindow>,
+    friday: Vec<DarkWindow>,
+    saturday: Vec<DarkWindow>,
+    sunday: Vec<DarkWindow>,
+}
+impl WeekdaySettings {
+    fn day(&self, weekday: Weekday) -> &Vec<DarkWindow> {
+        match weekday {
+            Weekday::Mon => &self.monday,
+            Weekday::Tue => &self.tuesday,
+            Weekday::Wed => &self.wednesday,
+            Weekday::Thu => &self.thursday,
+            Weekday::Fri => &self.friday,
+            Weekday::Sat => &self.saturday,
+            Weekday::Sun => &self.sunday,
+        }
+    }
+    fn day_mut(&mut self, weekday: Weekday) -> &mut Vec<DarkWindow> {
+        match weekday {
+            Weekday::Mon => &mut self.monday,
+            Weekday::Tue => &mut self.tuesday,
+            Weekday::Wed => &mut self.wednesday,
+            Weekday::Thu => &mut self.thursday,
+            Weekday::Fri => &mut self.friday,
+            Weekday::Sat => &mut self.saturday,
+            Weekday::Sun => &mut self.sunday,
+        }
+    }
+    // 一周每天的时间段，default 是每天相同或者工作日使用的时间段
+    fn week_windows(&self, default: &[DarkWindow]) -> WeekWindows {
+        let mut week = WeekWindows::every_day(default.iter().map(DarkWindow::rule));
+        match self.mode {
+            WeekdayMode::SameEveryDay => {}
+            WeekdayMode::WorkdaysWeekends => {
+                for weekday in [Weekday::Sat, Weekday::Sun] {
+                    week.set(weekday, self.weekend.iter().map(DarkWindow::rule));
+                }
+            }
+            WeekdayMode::PerDay => {
+                for weekday in WEEKDAYS {
+                    week.set(weekday, self.day(weekday).iter().map(DarkWindow::rule));
+                }
+            }
+        }
+        week.set_alternate(self.alternate.iter().map(DarkWindow::rule));
+        week
     }
+    // 界面上显示的时间范围
+    fn describe(&self, default: &[DarkWindow]) -> String {
+        let describe = |windows: &[DarkWindow]| {
+            let rules: Vec<WindowRule> = windows.iter().map(DarkWindow::rule).collect();
+            // 用到时间表达式时每天的长度不同，只列出设置
+            let Some(fixed) = rules
+                .iter()
+                .map(WindowRule::as_fixed)
+                .collect::<Option<Vec<_>>>()
+            else {
+                let rules: Vec<String> = rules.iter().map(ToString::to_string).collect();
+                return rules.join("、");
+            };
+            let windows = WindowSet::new(fixed);
+            let minutes = windows.duration().num_minutes();
+            format!(
+                "{}(共 {} 小时 {} 分钟)",
+                windows,
+                minutes / 60,
+                minutes % 60
+            )
+        };
+        match self.mode {
+            WeekdayMode::SameEveryDay => describe(default),
+            WeekdayMode::WorkdaysWeekends => format!(
+                "工作日 {}；周末 {}",
+                describe(default),
+                describe(&self.weekend)
+            ),
+            WeekdayMode::PerDay => WEEKDAYS
+                .iter()
+                .map(|&weekday| {
+                    format!("{} {}", weekday_label(weekday), describe(self.day(weekday)))
+                })
+                .collect::<Vec<_>>()
+                .join("；"),
+        }
+    }
+}
+// 一个季节的固定深色时间段，from 到 to 之间的日期(包含首尾两天)使用
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Season {
+    from: MonthDay,
+    to: MonthDay,
+    start: TimeExpr,
+    end: TimeExpr,
 }
-impl<'de> Deserialize<'de> for NaiveTimeWrapper {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let time_str = String::deserialize(deserializer)?;
-        let naive_time =
-            NaiveTime::parse_from_str(&time_str, "%H:%M:%S").map_err(serde::de::Error::custom)?;
-        Ok(NaiveTimeWrapper(naive_time))
+impl Season {
+    fn new(from: (u32, u32), to: (u32, u32), start: (u32, u32), end: (u32, u32)) -> Self {
+        let time = |(hh, mm)| TimeExpr::Time(NaiveTime::from_hms_opt(hh, mm, 0).unwrap());
+        Self {
+            from: MonthDay::new(from.0, from.1).unwrap(),
+            to: MonthDay::new(to.0, to.1).unwrap(),
+            start: time(start),
+            end: time(end),
+        }
+    }
+    fn range(&self) -> DateRange {
+        DateRange {
+            from: self.from,
+            to: self.to,
+        }
+    }
+    fn rule(&self) -> WindowRule {
+        WindowRule::new(self.start.clone(), self.end.clone())
     }
 }
-fn save_config_to_toml(config: &MyApp, filename: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let toml_string = toml::to_string_pretty(config)?;
-    let mut file = File::create(filename)?;
-    file.write_all(toml_string.as_bytes())?;
-    Ok(())
+// 按季节设置的深色时间段，启用后代替按星期设置的时间段
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct SeasonSettings {
+    enabled: bool,
+    seasons: Vec<Season>,
 }
-fn load_config_from_toml(filename: &str) -> Result<MyApp, Box<dyn std::error::Error>> {
-    let mut file = File::open(filename)?;
-    let mut toml_string = String::new();
-    file.read_to_string(&mut toml_string)?;
-    let config: MyApp = toml::from_str(&toml_string)?;
-    Ok(config)
+impl SeasonSettings {
+    // 检查各季节是否正好覆盖全年
+    fn validate(&self) -> Result<(), String> {
+        let ranges: Vec<DateRange> = self.seasons.iter().map(Season::range).collect();
+        season::validate_coverage(&ranges)
+    }
+    fn apply(&self, week: &mut WeekWindows) {
+        if self.enabled {
+            week.set_seasons(
+                self.seasons
+                    .iter()
+                    .map(|season| (season.range(), vec![season.rule()])),
+            );
+        }
+    }
+    // 界面上显示的时间范围，weekdays 是没有对应季节的日期使用的时间范围
+    fn describe(&self, weekdays: String) -> String {
+        if !self.enabled {
+            return weekdays;
+        }
+        let seasons = self
+            .seasons
+            .iter()
+            .map(|season| format!("{} {}", season.range(), season.rule()))
+            .collect::<Vec<_>>()
+            .join("；");
+        match self.validate() {
+            Ok(()) => format!("按季节 {}", seasons),
+            Err(_) => format!("按季节 {}；其他日期 {}", seasons, weekdays),
+        }
+    }
 }
 // fn on_config_change(config: &MyApp) {
 //     // 其他处理...
@@ -86,74 +283,856 @@ fn load_config_from_toml(filename: &str) -> Result<MyApp, Box<dyn std::error::Er
 //         eprintln!("Failed to save config: {}", e);
 //     }
 // }
+// 把以前保存在当前目录或程序旁边的配置复制到用户的配置目录，只复制能读取的配置
+fn migrate_legacy_config(path: &Path) {
+    for legacy in config::legacy_paths() {
+        if load_config_from_toml(&legacy).is_err() {
+            continue;
+        }
+        match config::migrate_legacy(&legacy, path) {
+            Ok(true) => eprintln!(
+                "Copied config from {} to {}",
+                legacy.display(),
+                path.display()
+            ),
+            // 不是旧版本的配置，或者新位置已经有配置
+            Ok(false) => continue,
+            Err(e) => eprintln!("Failed to copy config to {}: {}", path.display(), e),
+        }
+        return;
+    }
+}
+
+// 发布版是窗口程序，Windows 不会给它控制台。子命令和后台模式连接到
+// 启动它的终端，输出和错误信息才能显示出来
+#[cfg(windows)]
+fn attach_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    // 不是从终端启动时没有控制台可以连接，失败也没关系
+    unsafe { AttachConsole(ATTACH_PARENT_PROCESS) };
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
+
 fn main() -> Result<(), eframe::Error> {
-    env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
-    let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_inner_size([500.0, 500.0]),
-        ..Default::default()
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (config_flag, mode) = cli::take_config(&args)
+        .and_then(|(config_flag, args)| Ok((config_flag, cli::parse(&args)?)))
+        .unwrap_or_else(|e| {
+            attach_console();
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        });
+    if mode != cli::Mode::Gui {
+        attach_console();
+    }
+    // Log to stderr (if you run with `RUST_LOG=debug`). 后台模式默认记录每次切换
+    let default_level = if mode == cli::Mode::Daemon {
+        "info"
+    } else {
+        "error"
     };
-    let config_filename = "config.toml";
-    let config = load_config_from_toml(config_filename).unwrap_or_else(|e| {
-        eprintln!("Failed to load config: {}", e);
-
-        // 使用默认配置或者其他处理方式
-        // 注意：这里的示例代码中使用了 `Default::default()`，你可能需要根据你的实际情况修改这部分逻辑
-        Default::default()
-    });
-    eframe::run_native(
-        "深浅主题模式自动切换软件",
-        options,
-        Box::new(|cc| Box::new(MyApp::new(cc, config))), // 传递配置到 MyApp::new
-    )
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_level))
+        .init();
+    let (config_path, source) = config::locate(config_flag);
+    // 子命令只读取配置，不动其他位置的文件
+    if source == config::Source::User && !matches!(mode, cli::Mode::Command(_)) {
+        migrate_legacy_config(&config_path);
+    }
+    let mut config = match load_config_from_toml(&config_path) {
+        Ok(config) => config,
+        // 第一次运行时还没有配置文件
+        Err(_) if !config_path.exists() => MyApp::default(),
+        Err(e) => {
+            eprintln!("Failed to load config {}: {}", config_path.display(), e);
+            let mut config_error = format!("配置文件读取失败，当前使用默认设置：{}", e);
+            // 界面和后台模式会保存设置，覆盖之前先备份
+            if !matches!(mode, cli::Mode::Command(_)) {
+                match config::back_up(&config_path) {
+                    Ok(backup) => {
+                        config_error += &format!("。原来的文件已备份到 {}", backup.display())
+                    }
+                    Err(e) => eprintln!("Failed to back up config: {}", e),
+                }
+            }
+            MyApp {
+                config_error,
+                ..Default::default()
+            }
+        }
+    };
+    config.config_path = config_path;
+    config.guess_location_if_unset();
+    let backend = backend::select_backend(&config.backend_settings);
+    // 有子命令或者后台模式时不打开窗口
+    let result = match mode {
+        cli::Mode::Gui => {
+            // 已经有实例在运行时让它显示窗口，然后退出
+            let server = match ipc::claim(ipc::endpoint()) {
+                Ok(Instance::First(server)) => Some(server),
+                Ok(Instance::Running(mut client)) => {
+                    // 实例卡住时请求会超时，这时也直接退出，不再启动第二个实例
+                    if let Err(e) = client.request(&Request::Show) {
+                        attach_console();
+                        eprintln!("{}", e);
+                        std::process::exit(1);
+                    }
+                    return Ok(());
+                }
+                Err(e) => {
+                    eprintln!("Failed to start control interface: {}", e);
+                    None
+                }
+            };
+            let options = eframe::NativeOptions {
+                viewport: egui::ViewportBuilder::default().with_inner_size([500.0, 500.0]),
+                ..Default::default()
+            };
+            return eframe::run_native(
+                "深浅主题模式自动切换软件",
+                options,
+                Box::new(|cc| Box::new(MyApp::new(cc, config, backend, server))), // 传递配置到 MyApp::new
+            );
+        }
+        cli::Mode::Daemon => daemon::run(config, backend),
+        // 有实例在运行时交给它执行，否则直接修改
+        cli::Mode::Command(command) => match ipc::Client::connect(&ipc::endpoint()) {
+            Ok(mut client) => cli::run_remote(command, &mut client, &mut std::io::stdout()),
+            Err(_) => cli::run(command, &config, backend.as_ref(), &mut std::io::stdout()),
+        },
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    Ok(())
 }
-#[derive(Default, Debug, Serialize, Deserialize)]
+#[derive(Default, Debug)]
 struct MyApp {
     is_dark_mode: bool,
     is_system_dark_mode: bool,
     is_system_both_dark_mode: bool,
     auto_mode_change: bool, // 添加自动模式切换的标志
     auto_system_mode_change: bool,
-    custom_night_start: NaiveTimeWrapper, // 新增字段
-    custom_night_end: NaiveTimeWrapper,   // 新增字段
-    custom_night_start_hh: u32,
-    custom_night_start_mm: u32,
-    custom_night_end_hh: u32,
-    custom_night_end_mm: u32,
-    custom_system_night_start: NaiveTimeWrapper, // 新增字段
-    custom_system_night_end: NaiveTimeWrapper,   // 新增字段
-    custom_system_night_start_hh: u32,
-    custom_system_night_start_mm: u32,
-    custom_system_night_end_hh: u32,
-    custom_system_night_end_mm: u32,
+    // 深色模式时间段，可以有多个，例如午休和晚上
+    night_windows: Vec<DarkWindow>,
+    system_night_windows: Vec<DarkWindow>,
+    app_weekdays: WeekdaySettings,
+    system_weekdays: WeekdaySettings,
+    app_seasons: SeasonSettings,
+    system_seasons: SeasonSettings,
+    // 开启自动切换时手动选择的模式，过期前优先于定时计划
+    app_override: Option<Override>,
+    system_override: Option<Override>,
+    override_settings: OverrideSettings,
+    // 在系统设置等地方修改主题时的处理方式
+    external_change_policy: ExternalChangePolicy,
+    // 节假日等特殊日期，两个目标共用
+    date_exceptions: Vec<DateException>,
     is_autostart: bool,
+    location: Location,
+    // 位置的来源(城市名或时区)，为空表示手动输入
+    location_name: String,
+    app_solar: SolarSettings,
+    system_solar: SolarSettings,
+    backend_settings: BackendSettings,
+    backend: Option<Arc<dyn ThemeBackend>>,
+    scheduler: Option<Scheduler>,
+    reconciler: Arc<Mutex<Reconciler>>,
+    // 本机控制接口，其他进程通过它查询状态和切换模式
+    server: Option<Server>,
+    // 读取和保存配置的文件
+    config_path: PathBuf,
+    // 启动时读取配置失败的原因，显示在界面上
+    config_error: String,
+    city_query: String,
+    ics_path: String,
+    ics_kind: ExceptionKind,
+    ics_status: String,
 }
 
 impl MyApp {
-    fn new(cc: &eframe::CreationContext<'_>, config: MyApp) -> Self {
+    fn new(
+        cc: &eframe::CreationContext<'_>,
+        config: MyApp,
+        backend: Arc<dyn ThemeBackend>,
+        server: Option<Server>,
+    ) -> Self {
         setup_custom_fonts(&cc.egui_ctx);
+        // 主题被外部修改时刷新界面
+        let ctx = cc.egui_ctx.clone();
+        if let Err(e) = backend.watch(Box::new(move || ctx.request_repaint())) {
+            eprintln!("Failed to watch theme changes: {}", e);
+        }
+        // 定时切换在后台线程中进行，切换后刷新界面
+        let ctx = cc.egui_ctx.clone();
+        let app = MyApp::start(config, backend, move || ctx.request_repaint());
+        // 收到控制请求时刷新界面，在 update 中处理
+        if let Some(server) = &server {
+            let ctx = cc.egui_ctx.clone();
+            server.set_waker(move || ctx.request_repaint());
+        }
+        MyApp { server, ..app }
+    }
+    // 启动定时线程，界面和后台模式共用
+    fn start(
+        config: MyApp,
+        backend: Arc<dyn ThemeBackend>,
+        on_apply: impl Fn() + Send + 'static,
+    ) -> Self {
+        let reconciler = Arc::new(Mutex::new(Reconciler::default()));
+        let scheduler =
+            Scheduler::spawn(backend.clone(), reconciler.clone(), config.plan(), on_apply);
         Self {
-            is_dark_mode: config.is_dark_mode,
-            is_system_dark_mode: config.is_system_dark_mode,
-            auto_mode_change: config.auto_mode_change,
-            auto_system_mode_change: config.auto_system_mode_change,
-            is_system_both_dark_mode: config.is_system_both_dark_mode,
-            custom_night_start: config.custom_night_start,
-            custom_night_end: config.custom_night_end,
-            custom_night_start_hh: config.custom_night_start_hh,
-            custom_night_start_mm: config.custom_night_start_mm,
-            custom_night_end_hh: config.custom_night_end_hh,
-            custom_night_end_mm: config.custom_night_end_mm,
-            custom_system_night_start: config.custom_system_night_start,
-            custom_system_night_end: config.custom_system_night_end,
-            custom_system_night_start_hh: config.custom_system_night_start_hh,
-            custom_system_night_start_mm: config.custom_system_night_start_mm,
-            custom_system_night_end_hh: config.custom_system_night_end_hh,
-            custom_system_night_end_mm: config.custom_system_night_end_mm,
-            is_autostart: config.is_autostart,
+            backend: Some(backend),
+            scheduler: Some(scheduler),
+            reconciler,
+            ..config
+        }
+    }
+    // 还没有设置过位置时，按系统时区估计一个
+    fn guess_location_if_unset(&mut self) {
+        if self.location == Location::default() && self.location_name.is_empty() {
+            if let Some((timezone, location)) = geo::guess_location() {
+                self.location = location;
+                self.location_name = format!("系统时区 {}", timezone);
+            }
+        }
+    }
+    // 处理控制接口收到的请求
+    fn handle_request(&mut self, request: Request) -> Response {
+        let backend = self.backend();
+        let result = match request {
+            Request::Status => {
+                let paused = self.scheduler.as_ref().is_some_and(Scheduler::paused);
+                return Response::status(cli::status(self, backend.as_ref(), paused));
+            }
+            Request::Set { dark, target } => self.set_mode(backend.as_ref(), dark, target),
+            Request::Pause | Request::Resume => {
+                if let Some(scheduler) = &self.scheduler {
+                    scheduler.set_paused(request == Request::Pause);
+                }
+                Ok(())
+            }
+            Request::Reload => self.reload(),
+            // 有窗口时在 update 中处理
+            Request::Show => Err("已经有实例在后台模式运行，没有可以显示的窗口".into()),
+        };
+        match result {
+            Ok(()) => Response::ok(),
+            Err(e) => Response::error(e.to_string()),
+        }
+    }
+    // 和界面上的单选框一样，开启自动切换时保持到设定的时间
+    fn set_mode(
+        &mut self,
+        backend: &dyn ThemeBackend,
+        dark: bool,
+        target: Target,
+    ) -> Result<(), Box<dyn Error>> {
+        cli::check_writable(backend, target)?;
+        let app = target.app();
+        let system = target.system() || (app && self.is_system_both_dark_mode);
+        if app {
+            self.is_dark_mode = dark;
+        }
+        if system {
+            self.is_system_dark_mode = dark;
+        }
+        self.start_overrides(app, system);
+        let mut reconciler = self.reconciler.lock().unwrap();
+        if app {
+            reconciler.request_app(dark);
+        }
+        if system {
+            reconciler.request_system(dark);
+        }
+        reconciler.reconcile(backend);
+        drop(reconciler);
+        if let Some(scheduler) = &self.scheduler {
+            scheduler.set_plan(self.plan());
+        }
+        Ok(())
+    }
+    // 重新读取配置文件，保留后端、定时线程和控制接口
+    fn reload(&mut self) -> Result<(), Box<dyn Error>> {
+        let mut config = load_config_from_toml(&self.config_path)?;
+        config.guess_location_if_unset();
+        *self = MyApp {
+            backend: self.backend.take(),
+            scheduler: self.scheduler.take(),
+            reconciler: self.reconciler.clone(),
+            server: self.server.take(),
+            config_path: std::mem::take(&mut self.config_path),
+            ..config
+        };
+        if let Some(scheduler) = &self.scheduler {
+            scheduler.set_plan(self.plan());
         }
+        log::info!("Reloaded config from {}", self.config_path.display());
+        Ok(())
+    }
+    // 根据当前设置生成定时计划
+    fn plan(&self) -> Plan {
+        Plan {
+            app: self.auto_mode_change.then_some(self.app_schedule()),
+            system: self
+                .auto_system_mode_change
+                .then_some(self.system_schedule()),
+            system_follows_app: self.is_system_both_dark_mode,
+            app_override: self.app_override,
+            system_override: self.system_override,
+        }
+    }
+    // 开启自动切换时，界面上的手动切换保持到设定的时间
+    fn start_overrides(&mut self, app_changed: bool, system_changed: bool) {
+        let now = Local::now().naive_local();
+        if app_changed && self.auto_mode_change {
+            let next = self.app_schedule().next_change(now);
+            self.app_override = Some(self.override_settings.start(self.is_dark_mode, now, next));
+        }
+        if system_changed && self.auto_system_mode_change && !self.is_system_both_dark_mode {
+            let next = self.system_schedule().next_change(now);
+            self.system_override = Some(self.override_settings.start(
+                self.is_system_dark_mode,
+                now,
+                next,
+            ));
+        }
+        // 关闭自动切换或者已经过期的手动切换不再保留
+        let (auto_app, auto_system) = (self.auto_mode_change, self.auto_system_mode_change);
+        self.app_override = self.app_override.filter(|m| auto_app && m.active(now));
+        self.system_override = self
+            .system_override
+            .filter(|m| auto_system && m.active(now));
+    }
+    // 处理外部修改，更新定时计划并保存设置，界面每一帧和后台模式每次循环都会调用
+    fn sync(&mut self) -> ThemeState {
+        let backend = self.backend();
+        let applied = self.handle_external_changes(backend.as_ref());
+        if let Some(scheduler) = &self.scheduler {
+            scheduler.set_plan(self.plan());
+        }
+        if let Err(e) = save_config_to_toml(
+            self,
+            &self.config_path,
+            &mut self.reconciler.lock().unwrap(),
+        ) {
+            eprintln!("Failed to save config: {}", e);
+        }
+        applied
+    }
+    // 读取当前主题，按设定处理不是我们做的修改，返回处理后的状态
+    fn handle_external_changes(&mut self, backend: &dyn ThemeBackend) -> ThemeState {
+        let reconciler = self.reconciler.clone();
+        let mut reconciler = reconciler.lock().unwrap();
+        let applied = reconciler.observe(backend);
+        let changes = reconciler.take_external_changes();
+        // 两个目标设为相同时，任何一个被修改都按应用模式处理
+        let follows = self.is_system_both_dark_mode;
+        let app = changes.app.or(changes.system.filter(|_| follows));
+        let system = changes.system.filter(|_| !follows);
+        let now = Local::now().naive_local();
+        let respect = |schedule: Schedule, dark| Override {
+            dark,
+            until: schedule.next_change(now),
+        };
+        let mut reassert = false;
+        if let Some(dark) = app.filter(|_| self.auto_mode_change) {
+            eprintln!("App theme changed externally, dark: {}", dark);
+            match self.external_change_policy {
+                ExternalChangePolicy::RespectUntilNextTransition => {
+                    self.app_override = Some(respect(self.app_schedule(), dark));
+                }
+                ExternalChangePolicy::Reassert => reassert = true,
+                ExternalChangePolicy::DisableAutomation => self.auto_mode_change = false,
+            }
+        }
+        if let Some(dark) = system.filter(|_| self.auto_system_mode_change) {
+            eprintln!("System theme changed externally, dark: {}", dark);
+            match self.external_change_policy {
+                ExternalChangePolicy::RespectUntilNextTransition => {
+                    self.system_override = Some(respect(self.system_schedule(), dark));
+                }
+                ExternalChangePolicy::Reassert => reassert = true,
+                ExternalChangePolicy::DisableAutomation => self.auto_system_mode_change = false,
+            }
+        }
+        if !reassert {
+            return applied;
+        }
+        let (app, system) = self.plan().desired(now);
+        if let Some(dark) = app {
+            reconciler.request_app(dark);
+        }
+        if let Some(dark) = system {
+            reconciler.request_system(dark);
+        }
+        reconciler.reconcile(backend);
+        reconciler.observe(backend)
+    }
+    // 设定的固定深色时间段、季节和特殊日期
+    fn app_fixed_windows(&self) -> WeekWindows {
+        let mut week = self.app_weekdays.week_windows(&self.night_windows);
+        self.app_seasons.apply(&mut week);
+        week.set_location(self.location);
+        week.set_exceptions(&self.date_exceptions);
+        week
+    }
+    fn system_fixed_windows(&self) -> WeekWindows {
+        let mut week = self
+            .system_weekdays
+            .week_windows(&self.system_night_windows);
+        self.system_seasons.apply(&mut week);
+        week.set_location(self.location);
+        week.set_exceptions(&self.date_exceptions);
+        week
+    }
+    // 特殊日期的列表和从日历文件导入的控件
+    // 有日期设为使用备用时间段时才需要设置备用时间段
+    fn uses_alternate_windows(&self) -> bool {
+        self.date_exceptions
+            .iter()
+            .any(|exception| exception.kind == ExceptionKind::Alternate)
+    }
+
+    fn date_exceptions_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("特殊日期(优先于平常的深色时间段)");
+        ui.horizontal(|ui| {
+            ui.label("日历文件(.ics)");
+            ui.text_edit_singleline(&mut self.ics_path);
+            egui::ComboBox::from_id_source("ics_kind")
+                .selected_text(self.ics_kind.label())
+                .show_ui(ui, |ui| {
+                    for kind in ExceptionKind::ALL {
+                        ui.selectable_value(&mut self.ics_kind, kind, kind.label());
+                    }
+                });
+            if ui.button("导入").clicked() {
+                match exceptions::import_ics(Path::new(self.ics_path.trim()), self.ics_kind) {
+                    Ok(imported) => {
+                        self.ics_status = format!("已导入 {} 个日期", imported.exceptions.len());
+                        for warning in &imported.warnings {
+                            self.ics_status += &format!("\n{}", warning);
+                        }
+                        exceptions::merge(&mut self.date_exceptions, imported.exceptions);
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to import calendar: {}", e);
+                        self.ics_status = format!("导入失败：{}", e);
+                    }
+                }
+            }
+        });
+        if !self.ics_status.is_empty() {
+            ui.label(&self.ics_status);
+        }
+        let mut removed = None;
+        egui::ScrollArea::vertical()
+            .id_source("date_exceptions")
+            .max_height(120.0)
+            .show(ui, |ui| {
+                for (i, exception) in self.date_exceptions.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{} {}",
+                            exception.date.format("%Y-%m-%d"),
+                            exception.summary
+                        ));
+                        egui::ComboBox::from_id_source(("date_exception", i))
+                            .selected_text(exception.kind.label())
+                            .show_ui(ui, |ui| {
+                                for kind in ExceptionKind::ALL {
+                                    ui.selectable_value(&mut exception.kind, kind, kind.label());
+                                }
+                            });
+                        if ui.button("删除").clicked() {
+                            removed = Some(i);
+                        }
+                    });
+                }
+            });
+        if let Some(i) = removed {
+            self.date_exceptions.remove(i);
+        }
+        if !self.date_exceptions.is_empty() && ui.button("清空特殊日期").clicked() {
+            self.date_exceptions.clear();
+        }
+    }
+    fn app_schedule(&self) -> Schedule {
+        schedule(self.app_fixed_windows(), self.location, self.app_solar)
+    }
+    fn system_schedule(&self) -> Schedule {
+        schedule(
+            self.system_fixed_windows(),
+            self.location,
+            self.system_solar,
+        )
+    }
+    fn backend(&self) -> Arc<dyn ThemeBackend> {
+        self.backend.clone().expect("backend is set in MyApp::new")
     }
 }
 
+fn schedule(fixed: WeekWindows, location: Location, solar: SolarSettings) -> Schedule {
+    if solar.enabled {
+        Schedule::Solar {
+            location,
+            settings: solar,
+            fallback: fixed,
+        }
+    } else {
+        Schedule::Fixed(fixed)
+    }
+}
+
+// 深色时间段列表的控件，两个目标共用
+fn night_windows_ui(ui: &mut egui::Ui, id_source: &str, windows: &mut Vec<DarkWindow>) {
+    let mut removed = None;
+    for (i, window) in windows.iter_mut().enumerate() {
+        ui.push_id((id_source, i), |ui| {
+            ui.horizontal(|ui| {
+                ui.label(format!("深色时间段 {} 开始时间", i + 1));
+                time_ui(ui, "start", &mut window.start);
+                if ui.button("删除").clicked() {
+                    removed = Some(i);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label(format!("深色时间段 {} 结束时间", i + 1));
+                time_ui(ui, "end", &mut window.end);
+            });
+        });
+    }
+    if let Some(i) = removed {
+        windows.remove(i);
+    }
+    if ui.button("添加深色时间段").clicked() {
+        // 第一个时间段默认是晚上，之后的默认是午休
+        windows.push(if windows.is_empty() {
+            DarkWindow::new(18, 8)
+        } else {
+            DarkWindow::new(12, 13)
+        });
+    }
+    // 只检查固定时间的时间段，时间表达式每天不同
+    let ranges: Vec<TimeWindow> = windows
+        .iter()
+        .map(|window| window.rule().as_fixed().unwrap_or(TimeWindow::Empty))
+        .collect();
+    for (a, b) in overlapping_pairs(&ranges) {
+        ui.colored_label(
+            ui.visuals().warn_fg_color,
+            format!("深色时间段 {} 与 {} 重叠，将合并为一个时间段", a + 1, b + 1),
+        );
+    }
+}
+
+// 按星期设置深色时间段的控件，default 是每天相同或者工作日使用的时间段
+fn weekday_windows_ui(
+    ui: &mut egui::Ui,
+    id_source: &str,
+    default: &mut Vec<DarkWindow>,
+    weekdays: &mut WeekdaySettings,
+) {
+    let previous = weekdays.mode;
+    ui.horizontal(|ui| {
+        ui.label("按星期区分");
+        egui::ComboBox::from_id_source((id_source, "weekday_mode"))
+            .selected_text(weekdays.mode.label())
+            .show_ui(ui, |ui| {
+                for mode in WeekdayMode::ALL {
+                    ui.selectable_value(&mut weekdays.mode, mode, mode.label());
+                }
+            });
+    });
+    // 刚切换过来时，还没有设置的日子先使用当前的时间段
+    if weekdays.mode != previous {
+        match weekdays.mode {
+            WeekdayMode::SameEveryDay => {}
+            WeekdayMode::WorkdaysWeekends => {
+                if weekdays.weekend.is_empty() {
+                    weekdays.weekend = default.clone();
+                }
+            }
+            WeekdayMode::PerDay => {
+                for weekday in WEEKDAYS {
+                    let copied = match weekday {
+                        Weekday::Sat | Weekday::Sun
+                            if previous == WeekdayMode::WorkdaysWeekends =>
+                        {
+                            weekdays.weekend.clone()
+                        }
+                        _ => default.clone(),
+                    };
+                    let day = weekdays.day_mut(weekday);
+                    if day.is_empty() {
+                        *day = copied;
+                    }
+                }
+            }
+        }
+    }
+    match weekdays.mode {
+        WeekdayMode::SameEveryDay => night_windows_ui(ui, id_source, default),
+        WeekdayMode::WorkdaysWeekends => {
+            ui.label("工作日(周一至周五)");
+            night_windows_ui(ui, &format!("{}_workdays", id_source), default);
+            ui.label("周末(周六、周日)");
+            night_windows_ui(ui, &format!("{}_weekend", id_source), &mut weekdays.weekend);
+        }
+        WeekdayMode::PerDay => {
+            for weekday in WEEKDAYS {
+                let id = format!("{}_{}", id_source, weekday);
+                egui::CollapsingHeader::new(weekday_label(weekday))
+                    .id_source(&id)
+                    .show(ui, |ui| {
+                        night_windows_ui(ui, &id, weekdays.day_mut(weekday))
+                    });
+            }
+        }
+    }
+}
+
+// 特殊日期设为使用备用时间段时的时间段
+fn alternate_windows_ui(ui: &mut egui::Ui, id_source: &str, weekdays: &mut WeekdaySettings) {
+    ui.label("特殊日期的备用时间段");
+    night_windows_ui(
+        ui,
+        &format!("{}_alternate", id_source),
+        &mut weekdays.alternate,
+    );
+    if weekdays.alternate.is_empty() {
+        ui.colored_label(
+            ui.visuals().warn_fg_color,
+            "还没有设置备用时间段，使用备用时间段的日期全天都是浅色",
+        );
+    }
+}
+
+// 开启自动切换时手动切换后保持多久
+fn override_settings_ui(
+    ui: &mut egui::Ui,
+    settings: &mut OverrideSettings,
+    policy: &mut ExternalChangePolicy,
+) {
+    ui.horizontal(|ui| {
+        ui.label("自动切换时手动切换的模式保持");
+        egui::ComboBox::from_id_source("override_mode")
+            .selected_text(settings.mode.label())
+            .show_ui(ui, |ui| {
+                for mode in OverrideMode::ALL {
+                    ui.selectable_value(&mut settings.mode, mode, mode.label());
+                }
+            });
+        if settings.mode == OverrideMode::ForHours {
+            ui.add(
+                egui::DragValue::new(&mut settings.hours)
+                    .clamp_range(manual::HOURS)
+                    .suffix(" 小时"),
+            );
+        }
+    });
+    ui.horizontal(|ui| {
+        ui.label("在系统设置等地方修改主题时");
+        egui::ComboBox::from_id_source("external_change_policy")
+            .selected_text(policy.label())
+            .show_ui(ui, |ui| {
+                for option in ExternalChangePolicy::ALL {
+                    ui.selectable_value(policy, option, option.label());
+                }
+            });
+    });
+}
+
+// 正在生效的手动切换和取消按钮，取消后立即恢复定时计划
+fn override_status_ui(ui: &mut egui::Ui, manual: &mut Option<Override>) {
+    let Some(current) = *manual else {
+        return;
+    };
+    ui.horizontal(|ui| {
+        ui.label(current.to_string());
+        if ui.button("取消手动切换").clicked() {
+            *manual = None;
+        }
+    });
+}
+
+// 按季节设置深色时间段的控件
+fn seasons_ui(ui: &mut egui::Ui, id_source: &str, settings: &mut SeasonSettings) {
+    ui.checkbox(
+        &mut settings.enabled,
+        "按季节设置(代替上面按星期设置的时间段)",
+    );
+    if !settings.enabled {
+        return;
+    }
+    // 第一次启用时给出夏季和冬季两个例子
+    if settings.seasons.is_empty() {
+        settings.seasons = vec![
+            Season::new((4, 1), (9, 30), (21, 0), (6, 0)),
+            Season::new((10, 1), (3, 31), (17, 30), (7, 30)),
+        ];
+    }
+    let mut removed = None;
+    for (i, season) in settings.seasons.iter_mut().enumerate() {
+        ui.push_id((id_source, i), |ui| {
+            ui.horizontal(|ui| {
+                ui.label(format!("季节 {} 从", i + 1));
+                month_day_ui(ui, &mut season.from);
+                ui.label("到");
+                month_day_ui(ui, &mut season.to);
+                if ui.button("删除").clicked() {
+                    removed = Some(i);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("开始时间");
+                time_ui(ui, "start", &mut season.start);
+            });
+            ui.horizontal(|ui| {
+                ui.label("结束时间");
+                time_ui(ui, "end", &mut season.end);
+            });
+        });
+    }
+    if let Some(i) = removed {
+        settings.seasons.remove(i);
+    }
+    if ui.button("添加季节").clicked() {
+        settings
+            .seasons
+            .push(Season::new((1, 1), (12, 31), (18, 0), (8, 0)));
+    }
+    if let Err(e) = settings.validate() {
+        ui.colored_label(
+            ui.visuals().warn_fg_color,
+            format!("{}。没有对应季节的日期使用按星期设置的时间段", e),
+        );
+    }
+}
+
+fn month_day_ui(ui: &mut egui::Ui, date: &mut MonthDay) {
+    let mut month = date.month();
+    let mut day = date.day();
+    ui.add(
+        egui::DragValue::new(&mut month)
+            .clamp_range(1..=12)
+            .suffix("月"),
+    );
+    ui.add(
+        egui::DragValue::new(&mut day)
+            .clamp_range(1..=31)
+            .suffix("日"),
+    );
+    // 超出当月天数时取当月最后一天
+    if let Some(changed) = (1..=day).rev().find_map(|day| MonthDay::new(month, day)) {
+        *date = changed;
+    }
+}
+
+// 固定时间用滑块调整，也可以直接输入时间表达式，例如 max(sunset - 30m, 18:00)
+fn time_ui(ui: &mut egui::Ui, id_source: &str, time: &mut TimeExpr) {
+    if let Some(fixed) = time.as_time() {
+        let mut hour = fixed.hour();
+        let mut minute = fixed.minute();
+        ui.add(egui::Slider::new(&mut hour, 0..=23).text("小时"));
+        ui.add(egui::Slider::new(&mut minute, 0..=59).text("分钟"));
+        if (hour, minute) != (fixed.hour(), fixed.minute()) {
+            *time = TimeExpr::Time(NaiveTime::from_hms_opt(hour, minute, 0).unwrap());
+        }
+    }
+    // 输入中的文字存在界面状态里，解析成功才更新设置
+    let id = ui.make_persistent_id(id_source);
+    let mut text = ui
+        .data_mut(|data| data.get_temp::<String>(id))
+        .unwrap_or_else(|| time.to_string());
+    let response = ui.add(egui::TextEdit::singleline(&mut text).desired_width(180.0));
+    let parsed = text.parse::<TimeExpr>();
+    match &parsed {
+        Ok(expr) if response.changed() => *time = expr.clone(),
+        Ok(_) => {}
+        Err(e) => {
+            ui.colored_label(ui.visuals().error_fg_color, e.to_string());
+        }
+    }
+    // 没有在输入时跟随滑块的修改
+    if !response.has_focus() && parsed.is_ok() {
+        text = time.to_string();
+    }
+    ui.data_mut(|data| data.insert_temp(id, text));
+}
+
+// 日出日落设置的控件，两个目标共用
+fn solar_settings_ui(ui: &mut egui::Ui, id_source: &str, settings: &mut SolarSettings) {
+    ui.checkbox(&mut settings.enabled, "按日出日落时间切换");
+    if settings.enabled {
+        ui.horizontal(|ui| {
+            ui.label("切换时机");
+            egui::ComboBox::from_id_source(id_source)
+                .selected_text(settings.event.label())
+                .show_ui(ui, |ui| {
+                    for event in SolarEvent::ALL {
+                        ui.selectable_value(&mut settings.event, event, event.label());
+                    }
+                });
+            if settings.event == SolarEvent::Custom {
+                ui.add(
+                    egui::DragValue::new(&mut settings.custom_elevation)
+                        .clamp_range(-18.0..=10.0)
+                        .speed(0.1)
+                        .suffix("°"),
+                );
+            }
+        });
+        ui.add(
+            egui::Slider::new(&mut settings.sunset_offset_minutes, -180..=180)
+                .text("日落偏移(分钟)"),
+        );
+        ui.add(
+            egui::Slider::new(&mut settings.sunrise_offset_minutes, -180..=180)
+                .text("日出偏移(分钟)"),
+        );
+        ui.horizontal(|ui| {
+            ui.label("没有日出日落时(极昼、极夜)");
+            egui::ComboBox::from_id_source((id_source, "polar"))
+                .selected_text(settings.polar_policy.label())
+                .show_ui(ui, |ui| {
+                    for policy in PolarPolicy::ALL {
+                        ui.selectable_value(&mut settings.polar_policy, policy, policy.label());
+                    }
+                });
+        });
+    }
+}
+
+// 设定的深色模式时间范围，启用日出日落时说明极昼极夜的处理方式
+fn dark_range_label(prefix: &str, fixed: &str, solar: &SolarSettings) -> String {
+    if !solar.enabled {
+        return format!("{}{}", prefix, fixed);
+    }
+    let polar = match solar.polar_policy {
+        PolarPolicy::FixedTimes => format!("{} {}", solar.polar_policy.label(), fixed),
+        policy => policy.label().to_owned(),
+    };
+    format!(
+        "{}按{}切换，没有日出日落时{}",
+        prefix,
+        solar.event.label(),
+        polar
+    )
+}
+
+// 今天按计划使用的深色模式时间范围
+fn today_window_label(prefix: &str, schedule: &Schedule) -> String {
+    format!(
+        "{}{}",
+        prefix,
+        schedule.window_on(Local::now().date_naive())
+    )
+}
+
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // if self.config_changed {
@@ -161,22 +1140,67 @@ impl eframe::App for MyApp {
         //     // 可以在这里重置标志，表示已经处理了配置更改
         //     self.config_changed = false;
         // }
-        save_config_to_toml(self, "config.toml").expect("TODO: panic message");
+        // 先处理控制请求，修改后的设置在下面一起保存
+        while let Some(incoming) = self.server.as_ref().and_then(Server::try_recv) {
+            let response = match incoming.request {
+                Request::Show => {
+                    // 再次启动程序时把窗口带到前面
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Visible(true));
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+                    ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+                    Response::ok()
+                }
+                request => self.handle_request(request),
+            };
+            incoming.reply(response);
+        }
         // 在每次更新时检查主题模式并更新界面
-        self.is_dark_mode = is_dark_mode_enabled();
-        self.is_system_dark_mode = is_system_dark_mode_enabled();
-        let mut tempautostart = self.is_autostart;
+        let applied = self.sync();
+        let backend = self.backend();
+        let backend = backend.as_ref();
+        // 读取失败时按浅色处理
+        self.is_dark_mode = applied.app.unwrap_or(false);
+        self.is_system_dark_mode = applied.system.unwrap_or(false);
+        let capabilities = backend.capabilities();
+        let tempautostart = self.is_autostart;
+        let was_dark_mode = self.is_dark_mode;
+        let was_system_dark_mode = self.is_system_dark_mode;
+        let was_system_both_dark_mode = self.is_system_both_dark_mode;
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("深浅主题模式自动切换软件");
+            if !self.config_error.is_empty() {
+                ui.horizontal_wrapped(|ui| {
+                    ui.colored_label(ui.visuals().error_fg_color, &self.config_error);
+                    if ui.button("知道了").clicked() {
+                        self.config_error.clear();
+                    }
+                });
+            }
+            if let Some(scheduler) = self.scheduler.as_ref().filter(|s| s.paused()) {
+                ui.horizontal(|ui| {
+                    ui.label("自动切换已通过命令行暂停");
+                    if ui.button("恢复自动切换").clicked() {
+                        scheduler.set_paused(false);
+                    }
+                });
+            }
             ui.group(|ui| ui.horizontal(|ui| ui.checkbox(&mut self.is_autostart, "是否开机启动")));
             if self.is_autostart != tempautostart {
-                if self.is_autostart {
-                    option_env!("CARGO_PKG_NAME").map(|app_name| {
-                        set_autostart(app_name, &env::current_exe().unwrap().to_str().unwrap())
+                let app_name = env!("CARGO_PKG_NAME");
+                let result = registry::current_user()
+                    .map_err(|e| e.into())
+                    .and_then(|reg| {
+                        if self.is_autostart {
+                            let path = env::current_exe()?;
+                            set_autostart(reg.as_ref(), app_name, &path.to_string_lossy())
+                        } else {
+                            // 取消开机启动项
+                            remove_startup_entry(reg.as_ref(), app_name)
+                        }
                     });
-                } else {
-                    // 取消开机启动项
-                    option_env!("CARGO_PKG_NAME").map(|app_name| remove_startup_entry(app_name));
+                if let Err(e) = result {
+                    eprintln!("Failed to update autostart: {}", e);
+                    self.is_autostart = tempautostart;
                 }
             }
 
@@ -190,6 +1214,66 @@ impl eframe::App for MyApp {
                 });
             });
 
+            ui.group(|ui| {
+                override_settings_ui(
+                    ui,
+                    &mut self.override_settings,
+                    &mut self.external_change_policy,
+                )
+            });
+
+            ui.group(|ui| {
+                ui.horizontal(|ui| {
+                    ui.label("所在位置(用于计算日出日落)");
+                    let latitude = ui.add(
+                        egui::DragValue::new(&mut self.location.latitude)
+                            .clamp_range(-90.0..=90.0)
+                            .speed(0.1)
+                            .prefix("纬度 "),
+                    );
+                    let longitude = ui.add(
+                        egui::DragValue::new(&mut self.location.longitude)
+                            .clamp_range(-180.0..=180.0)
+                            .speed(0.1)
+                            .prefix("经度 "),
+                    );
+                    if latitude.changed() || longitude.changed() {
+                        self.location_name.clear();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if self.location_name.is_empty() {
+                        ui.label("当前位置：手动输入");
+                    } else {
+                        ui.label(format!("当前位置：{}", self.location_name));
+                    }
+                    if ui.button("按系统时区估计").clicked() {
+                        if let Some((timezone, location)) = geo::guess_location() {
+                            self.location = location;
+                            self.location_name = format!("系统时区 {}", timezone);
+                        }
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("搜索城市");
+                    ui.text_edit_singleline(&mut self.city_query);
+                });
+                ui.horizontal_wrapped(|ui| {
+                    for city in geo::search_cities(&self.city_query).into_iter().take(8) {
+                        if ui
+                            .button(format!("{} {}", city.local_name, city.name))
+                            .clicked()
+                        {
+                            self.location = city.location;
+                            self.location_name = format!("{} {}", city.local_name, city.name);
+                            self.city_query.clear();
+                        }
+                    }
+                });
+            });
+
+            ui.group(|ui| self.date_exceptions_ui(ui));
+
             ui.group(|ui| {
                 // 在这里添加您的控件
                 // 添加单选框来切换颜色模式
@@ -210,53 +1294,37 @@ impl eframe::App for MyApp {
                         ui.label("自动切换默认应用模式(常规应用)已启用");
                     }
                 });
+                override_status_ui(ui, &mut self.app_override);
 
-                ui.horizontal(|ui| {
-                    ui.add(egui::Label::new("自定义深色模式开始时间"));
-                    ui.add(egui::Slider::new(&mut self.custom_night_start_hh, 0..=23).text("小时"));
-                    ui.add(egui::Slider::new(&mut self.custom_night_start_mm, 0..=59).text("分钟"));
-                });
-                ui.horizontal(|ui| {
-                    ui.add(egui::Label::new("自定义深色模式结束时间"));
-                    ui.add(egui::Slider::new(&mut self.custom_night_end_hh, 0..=23).text("小时"));
-                    ui.add(egui::Slider::new(&mut self.custom_night_end_mm, 0..=59).text("分钟"));
-                });
-                self.custom_night_start = NaiveTimeWrapper(
-                    NaiveTime::from_hms_opt(
-                        self.custom_night_start_hh,
-                        self.custom_night_start_mm,
-                        0,
-                    )
-                    .unwrap(),
-                );
-                self.custom_night_end = NaiveTimeWrapper(
-                    NaiveTime::from_hms_opt(self.custom_night_end_hh, self.custom_night_end_mm, 0)
-                        .unwrap(),
+                weekday_windows_ui(
+                    ui,
+                    "app_night_windows",
+                    &mut self.night_windows,
+                    &mut self.app_weekdays,
                 );
+                if self.uses_alternate_windows() {
+                    alternate_windows_ui(ui, "app_night_windows", &mut self.app_weekdays);
+                }
+                seasons_ui(ui, "app_seasons", &mut self.app_seasons);
 
-                // 如果启用了自动模式切换，并且当前时间在指定的范围内，则切换主题模式
-                if self.auto_mode_change {
-                    let current_time = Local::now().time();
+                solar_settings_ui(ui, "app_solar_event", &mut self.app_solar);
 
-                    if current_time >= self.custom_night_start.0
-                        || current_time < self.custom_night_end.0
-                    {
-                        self.is_dark_mode = true;
-                        set_dark_mode(true);
-                    } else {
-                        self.is_dark_mode = false;
-                        set_dark_mode(false);
-                    }
-                }
                 ui.add_space(10.0);
-                ui.add(egui::Label::new(
-                    "当前设定的默认应用模式(常规应用)深色模式时间范围是：".to_owned()
-                        + &self.custom_night_start.to_string()
-                        + " - "
-                        + &self.custom_night_end.to_string(),
-                ));
+                ui.add(egui::Label::new(dark_range_label(
+                    "当前设定的默认应用模式(常规应用)深色模式时间范围是：",
+                    &self
+                        .app_seasons
+                        .describe(self.app_weekdays.describe(&self.night_windows)),
+                    &self.app_solar,
+                )));
+                if self.app_solar.enabled {
+                    ui.label(today_window_label(
+                        "今天按日出日落计算的时间范围是：",
+                        &self.app_schedule(),
+                    ));
+                }
             });
-            if !self.is_system_both_dark_mode {
+            if !self.is_system_both_dark_mode && capabilities.separate_system_mode {
                 ui.group(|ui| {
                     ui.horizontal(|ui| {
                         ui.label("默认windows模式(任务栏)");
@@ -275,75 +1343,58 @@ impl eframe::App for MyApp {
                             ui.label("自动切换默认windows模式(任务栏)已启用");
                         }
                     });
-                    ui.horizontal(|ui| {
-                        ui.add(egui::Label::new("自定义深色模式开始时间"));
-                        ui.add(
-                            egui::Slider::new(&mut self.custom_system_night_start_hh, 0..=23)
-                                .text("小时"),
-                        );
-                        ui.add(
-                            egui::Slider::new(&mut self.custom_system_night_start_mm, 0..=59)
-                                .text("分钟"),
-                        );
-                    });
-                    ui.horizontal(|ui| {
-                        ui.add(egui::Label::new("自定义深色模式结束时间"));
-                        ui.add(
-                            egui::Slider::new(&mut self.custom_system_night_end_hh, 0..=23)
-                                .text("小时"),
-                        );
-                        ui.add(
-                            egui::Slider::new(&mut self.custom_system_night_end_mm, 0..=59)
-                                .text("分钟"),
-                        );
-                    });
-                    self.custom_system_night_start = NaiveTimeWrapper(
-                        NaiveTime::from_hms_opt(
-                            self.custom_system_night_start_hh,
-                            self.custom_system_night_start_mm,
-                            0,
-                        )
-                        .unwrap(),
-                    );
-
-                    self.custom_system_night_end = NaiveTimeWrapper(
-                        NaiveTime::from_hms_opt(
-                            self.custom_system_night_end_hh,
-                            self.custom_system_night_end_mm,
-                            0,
-                        )
-                        .unwrap(),
+                    override_status_ui(ui, &mut self.system_override);
+                    weekday_windows_ui(
+                        ui,
+                        "system_night_windows",
+                        &mut self.system_night_windows,
+                        &mut self.system_weekdays,
                     );
+                    if self.uses_alternate_windows() {
+                        alternate_windows_ui(ui, "system_night_windows", &mut self.system_weekdays);
+                    }
+                    seasons_ui(ui, "system_seasons", &mut self.system_seasons);
 
-                    // 如果启用了自动模式切换，并且当前时间在指定的范围内，则切换主题模式
-                    if self.auto_system_mode_change {
-                        let current_time = Local::now().time();
+                    solar_settings_ui(ui, "system_solar_event", &mut self.system_solar);
 
-                        if current_time >= self.custom_system_night_start.0
-                            || current_time < self.custom_system_night_end.0
-                        {
-                            self.is_system_dark_mode = true;
-                            set_system_dark_mode(true);
-                        } else {
-                            self.is_system_dark_mode = false;
-                            set_system_dark_mode(false);
-                        }
-                    }
                     ui.add_space(10.0);
-                    ui.add(egui::Label::new(
-                        "当前设定的默认windows模式(任务栏)深色模式时间范围是：".to_owned()
-                            + &self.custom_system_night_start.to_string()
-                            + " - "
-                            + &self.custom_system_night_end.to_string(),
-                    ));
+                    ui.add(egui::Label::new(dark_range_label(
+                        "当前设定的默认windows模式(任务栏)深色模式时间范围是：",
+                        &self
+                            .system_seasons
+                            .describe(self.system_weekdays.describe(&self.system_night_windows)),
+                        &self.system_solar,
+                    )));
+                    if self.system_solar.enabled {
+                        ui.label(today_window_label(
+                            "今天按日出日落计算的时间范围是：",
+                            &self.system_schedule(),
+                        ));
+                    }
                 });
             }
 
-            // 如果用户改变了设置，则更新系统的主题模式
-            set_dark_mode(self.is_dark_mode);
-            set_system_dark_mode(self.is_system_dark_mode);
+            self.start_overrides(
+                self.is_dark_mode != was_dark_mode,
+                self.is_system_dark_mode != was_system_dark_mode,
+            );
+            // 如果用户改变了设置，则更新系统的主题模式；
+            // 只在有变化时写入，避免覆盖定时线程刚刚做的切换
+            let mut reconciler = self.reconciler.lock().unwrap();
+            if self.is_dark_mode != was_dark_mode {
+                reconciler.request_app(self.is_dark_mode);
+            }
             if self.is_system_both_dark_mode {
-                set_system_dark_mode(self.is_dark_mode);
+                if self.is_dark_mode != was_dark_mode || !was_system_both_dark_mode {
+                    reconciler.request_system(self.is_dark_mode);
+                }
+            } else if self.is_system_dark_mode != was_system_dark_mode {
+                reconciler.request_system(self.is_system_dark_mode);
+            }
+            reconciler.reconcile(backend);
+            drop(reconciler);
+            if let Some(scheduler) = &self.scheduler {
+                scheduler.set_plan(self.plan());
             }
             // 显示主题信息
             ui.horizontal(|ui| {
@@ -363,61 +1414,30 @@ impl eframe::App for MyApp {
                     ui.label("浅色模式");
                 }
             });
+            ui.label(format!("当前主题后端：{}", backend.name()));
+            ui.collapsing("调试信息", |ui| {
+                let counters = self.reconciler.lock().unwrap().counters();
+                ui.label(format!(
+                    "写入默认应用模式 {} 次，写入默认windows模式 {} 次，跳过 {} 次，失败 {} 次",
+                    counters.app_writes,
+                    counters.system_writes,
+                    counters.skipped_writes,
+                    counters.failed_writes
+                ));
+                ui.label(format!("配置文件：{}", self.config_path.display()));
+                ui.label(format!(
+                    "保存配置 {} 次，失败 {} 次",
+                    counters.config_saves, counters.failed_config_saves
+                ));
+            });
+            if !capabilities.separate_system_mode {
+                ui.label(
+                    "当前后端只有一个配色设置，默认windows模式(任务栏)与默认应用模式(常规应用)相同",
+                );
+            }
         });
     }
 }
-// 使用 Windows API 检查系统是否处于深色模式
-fn is_dark_mode_enabled() -> bool {
-    // stdout.contains("0x0")
-    let helm = RegKey::predef(HKEY_CURRENT_USER);
-    if let Ok(sub_key) = helm.open_subkey_with_flags(
-        r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize",
-        KEY_READ,
-    ) {
-        if let Ok(value) = sub_key.get_value::<u32, _>("AppsUseLightTheme") {
-            return value == 0;
-        }
-    }
-    // Return false by default if there was an error or the value doesn't exist
-    false
-}
-fn is_system_dark_mode_enabled() -> bool {
-    // stdout.contains("0x0")
-    let helm = RegKey::predef(HKEY_CURRENT_USER);
-    if let Ok(sub_key) = helm.open_subkey_with_flags(
-        r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize",
-        KEY_READ,
-    ) {
-        if let Ok(value) = sub_key.get_value::<u32, _>("SystemUsesLightTheme") {
-            return value == 0;
-        }
-    }
-    // Return false by default if there was an error or the value doesn't exist
-    false
-}
-fn set_dark_mode(enabled: bool) {
-    let check = RegKey::predef(HKEY_CURRENT_USER)
-        .open_subkey_with_flags(
-            "Software\\Microsoft\\Windows\\CurrentVersion\\Themes\\Personalize",
-            winreg::enums::KEY_SET_VALUE,
-        )
-        .unwrap();
-
-    let value = if enabled { 0u32 } else { 1u32 };
-    check.set_value("AppsUseLightTheme", &value).unwrap();
-}
-
-fn set_system_dark_mode(enabled: bool) {
-    let check = RegKey::predef(HKEY_CURRENT_USER)
-        .open_subkey_with_flags(
-            "Software\\Microsoft\\Windows\\CurrentVersion\\Themes\\Personalize",
-            winreg::enums::KEY_SET_VALUE,
-        )
-        .unwrap();
-
-    let value = if enabled { 0u32 } else { 1u32 };
-    check.set_value("SystemUsesLightTheme", &value).unwrap();
-}
 fn setup_custom_fonts(ctx: &egui::Context) {
     // Start with the default fonts (we will be adding to them rather than replacing them).
     let mut fonts = egui::FontDefinitions::default();
@@ -446,35 +1466,232 @@ fn setup_custom_fonts(ctx: &egui::Context) {
     ctx.set_fonts(fonts);
 }
 
-fn set_autostart(app_name: &str, app_path: &str) -> Result<(), Box<dyn Error>> {
-    // 打开注册表项
-    let hklm = RegKey::predef(HKEY_CURRENT_USER);
-    let key_path = "Software\\Microsoft\\Windows\\CurrentVersion\\Run";
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // 创建或打开注册表项
-    let (key, _) = hklm.create_subkey_with_flags(key_path, KEY_SET_VALUE)?;
+    fn save(config: &MyApp) -> String {
+        toml::to_string_pretty(&Config::from(config)).unwrap()
+    }
 
-    // 设置注册表项值
-    key.set_value(app_name, &app_path)?;
+    fn load(content: &str) -> MyApp {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, content).unwrap();
+        load_config_from_toml(&path).unwrap()
+    }
 
-    // println!("成功添加 {} 到开机启动项。", app_name);
+    #[test]
+    fn single_window_config_is_migrated_to_a_list() {
+        let config = load(
+            r#"
+is_dark_mode = false
+is_system_dark_mode = false
+is_system_both_dark_mode = false
+auto_mode_change = true
+auto_system_mode_change = false
+custom_night_start = "19:30:00"
+custom_night_end = "07:00:00"
+custom_night_start_hh = 19
+custom_night_start_mm = 30
+custom_night_end_hh = 7
+custom_night_end_mm = 0
+custom_system_night_start = "18:00:00"
+custom_system_night_end = "08:00:00"
+custom_system_night_start_hh = 18
+custom_system_night_start_mm = 0
+custom_system_night_end_hh = 8
+custom_system_night_end_mm = 0
+is_autostart = false
+"#,
+        );
+        assert_eq!(config.night_windows.len(), 1);
+        assert_eq!(
+            config.night_windows[0].start,
+            NaiveTime::from_hms_opt(19, 30, 0).unwrap().into()
+        );
+        assert_eq!(
+            config.night_windows[0]
+                .end
+                .as_time()
+                .map(|time| time.hour()),
+            Some(7)
+        );
+        assert_eq!(config.system_night_windows, [DarkWindow::new(18, 8)]);
 
-    Ok(())
-}
-fn remove_startup_entry(app_name: &str) -> Result<(), Box<dyn Error>> {
-    // 打开注册表项
-    let hklm = RegKey::predef(HKEY_CURRENT_USER);
-    let key_path = "Software\\Microsoft\\Windows\\CurrentVersion\\Run";
+        // 保存后不再包含旧的字段，再次读取结果相同
+        let saved = save(&config);
+        assert!(!saved.contains("custom_night_start"));
+        let reloaded = load(&saved);
+        assert_eq!(reloaded.night_windows, config.night_windows);
+        assert_eq!(reloaded.system_night_windows, config.system_night_windows);
+    }
 
-    // 打开注册表项
-    let key = hklm.open_subkey(key_path)?;
+    #[test]
+    fn weekday_settings_round_trip() {
+        let mut config = MyApp {
+            night_windows: vec![DarkWindow::new(22, 7)],
+            ..Default::default()
+        };
+        config.app_weekdays.mode = WeekdayMode::WorkdaysWeekends;
+        config.app_weekdays.weekend = vec![DarkWindow::new(23, 10), DarkWindow::new(13, 14)];
+        config.app_weekdays.friday = vec![DarkWindow::new(23, 9)];
+        let reloaded = load(&save(&config));
+        assert_eq!(reloaded.app_weekdays, config.app_weekdays);
+        assert_eq!(reloaded.system_weekdays, WeekdaySettings::default());
 
-    // 删除注册表项值
-    if let Err(err) = key.delete_value(app_name) {
-        // eprintln!("删除注册表项值时出错：{}", err);
-    } else {
-        // println!("成功取消开机启动项 {}。", app_name);
+        // 工作日使用 night_windows，周末使用 weekend，每天分别设置的内容此时不生效
+        let week = reloaded.app_fixed_windows();
+        let friday = chrono::NaiveDate::from_ymd_opt(2024, 1, 5).unwrap();
+        let at = |hour| NaiveTime::from_hms_opt(hour, 0, 0).unwrap();
+        assert!(week.on(friday).contains(at(22)));
+        let saturday = friday.succ_opt().unwrap();
+        assert!(!week.on(saturday).contains(at(8)));
+        assert!(week.on(saturday).contains(at(13)));
+        assert!(week
+            .on(saturday + chrono::Duration::days(1))
+            .contains(at(9)));
     }
 
-    Ok(())
+    #[test]
+    fn seasons_round_trip_and_override_weekdays() {
+        let mut config = MyApp {
+            night_windows: vec![DarkWindow::new(22, 7)],
+            ..Default::default()
+        };
+        config.app_seasons = SeasonSettings {
+            enabled: true,
+            seasons: vec![
+                Season::new((4, 1), (9, 30), (21, 0), (6, 0)),
+                Season::new((10, 1), (3, 31), (17, 30), (7, 30)),
+            ],
+        };
+        let saved = save(&config);
+        assert!(saved.contains("from = \"10-01\""), "{}", saved);
+        assert!(saved.contains("start = \"17:30:00\""), "{}", saved);
+        let reloaded = load(&saved);
+        assert_eq!(reloaded.app_seasons, config.app_seasons);
+        assert_eq!(reloaded.app_seasons.validate(), Ok(()));
+
+        let week = reloaded.app_fixed_windows();
+        let date = |month, day| chrono::NaiveDate::from_ymd_opt(2024, month, day).unwrap();
+        let at = |hour, minute| NaiveTime::from_hms_opt(hour, minute, 0).unwrap();
+        assert!(!week.on(date(7, 1)).contains(at(20, 0)));
+        assert!(week.on(date(7, 1)).contains(at(21, 0)));
+        assert!(week.on(date(1, 15)).contains(at(17, 30)));
+        assert!(week.on(date(1, 15)).contains(at(7, 15)));
+
+        // 关闭后恢复按星期设置的时间段
+        config.app_seasons.enabled = false;
+        assert!(!config
+            .app_fixed_windows()
+            .on(date(1, 15))
+            .contains(at(17, 30)));
+        // 覆盖不全时提示缺少的日期
+        config.app_seasons.seasons.pop();
+        let error = config.app_seasons.validate().unwrap_err();
+        assert_eq!(
+            error,
+            "01-01 至 03-31 没有对应的季节；10-01 至 12-31 没有对应的季节"
+        );
+    }
+
+    #[test]
+    fn alternate_windows_apply_on_alternate_dates() {
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 12, 31).unwrap();
+        let mut config = MyApp {
+            night_windows: vec![DarkWindow::new(22, 7)],
+            date_exceptions: vec![DateException {
+                date,
+                kind: ExceptionKind::AlwaysLight,
+                summary: String::new(),
+            }],
+            ..Default::default()
+        };
+        assert!(!config.uses_alternate_windows());
+        config.date_exceptions[0].kind = ExceptionKind::Alternate;
+        assert!(config.uses_alternate_windows());
+        config.app_weekdays.alternate = vec![DarkWindow::new(20, 6)];
+        let reloaded = load(&save(&config));
+        assert_eq!(
+            reloaded.app_weekdays.alternate,
+            config.app_weekdays.alternate
+        );
+
+        let week = reloaded.app_fixed_windows();
+        let at = |hour| NaiveTime::from_hms_opt(hour, 0, 0).unwrap();
+        assert!(week.on(date).contains(at(21)));
+        assert!(!week.on(date.pred_opt().unwrap()).contains(at(21)));
+    }
+
+    #[test]
+    fn override_survives_a_restart() {
+        let until = chrono::NaiveDate::from_ymd_opt(2024, 1, 15)
+            .unwrap()
+            .and_hms_opt(18, 0, 0)
+            .unwrap();
+        let config = MyApp {
+            auto_mode_change: true,
+            app_override: Some(Override {
+                dark: true,
+                until: Some(until),
+            }),
+            override_settings: OverrideSettings {
+                mode: OverrideMode::ForHours,
+                hours: 3,
+            },
+            ..Default::default()
+        };
+        let saved = save(&config);
+        let reloaded = load(&saved);
+        assert_eq!(reloaded.app_override, config.app_override);
+        assert_eq!(reloaded.system_override, None);
+        assert_eq!(reloaded.override_settings, config.override_settings);
+        assert_eq!(reloaded.plan().app_override, config.app_override);
+        // 以前的配置没有手动切换的设置
+        assert_eq!(
+            load("is_dark_mode = false\nis_system_dark_mode = false\nis_system_both_dark_mode = false\nauto_mode_change = false\nauto_system_mode_change = false\nis_autostart = false\n")
+                .override_settings,
+            OverrideSettings::default()
+        );
+    }
+
+    #[test]
+    fn external_changes_follow_the_policy() {
+        let run = |policy| {
+            let backend = backend::MemoryBackend::default();
+            let mut app = MyApp {
+                auto_mode_change: true,
+                // 全天浅色，定时计划不会再切换
+                night_windows: Vec::new(),
+                external_change_policy: policy,
+                ..Default::default()
+            };
+            // 第一次读取只记录当前状态
+            app.handle_external_changes(&backend);
+            backend.set_dark_mode(true).unwrap();
+            let applied = app.handle_external_changes(&backend);
+            (app, applied)
+        };
+
+        let (app, applied) = run(ExternalChangePolicy::RespectUntilNextTransition);
+        assert_eq!(applied.app, Some(true));
+        assert_eq!(
+            app.app_override,
+            Some(Override {
+                dark: true,
+                until: None
+            })
+        );
+        assert!(app.auto_mode_change);
+
+        let (app, applied) = run(ExternalChangePolicy::Reassert);
+        assert_eq!(applied.app, Some(false));
+        assert_eq!(app.app_override, None);
+
+        let (app, applied) = run(ExternalChangePolicy::DisableAutomation);
+        assert_eq!(applied.app, Some(true));
+        assert!(!app.auto_mode_change);
+        assert_eq!(app.plan().app, None);
+    }
 }
@@ -0,0 +1,235 @@
+// 开启自动切换时手动选择的模式，在一段时间内优先于定时计划
+use chrono::{Duration, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// 手动切换后保持多久
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverrideMode {
+    // 到下一次定时切换为止
+    #[default]
+    UntilNextTransition,
+    // 保持 hours 小时
+    ForHours,
+    // 到第二天 0 点
+    UntilTomorrow,
+}
+
+impl OverrideMode {
+    pub const ALL: [OverrideMode; 3] = [
+        OverrideMode::UntilNextTransition,
+        OverrideMode::ForHours,
+        OverrideMode::UntilTomorrow,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            OverrideMode::UntilNextTransition => "到下一次定时切换",
+            OverrideMode::ForHours => "保持几个小时",
+            OverrideMode::UntilTomorrow => "到明天",
+        }
+    }
+}
+
+// 按小时保持时可以设置的范围
+pub const HOURS: std::ops::RangeInclusive<u32> = 1..=24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OverrideSettings {
+    pub mode: OverrideMode,
+    pub hours: u32,
+}
+
+impl Default for OverrideSettings {
+    fn default() -> Self {
+        Self {
+            mode: OverrideMode::default(),
+            hours: 2,
+        }
+    }
+}
+
+impl OverrideSettings {
+    // now 时手动切换到 dark，next_transition 是定时计划的下一次切换时间
+    pub fn start(
+        &self,
+        dark: bool,
+        now: NaiveDateTime,
+        next_transition: Option<NaiveDateTime>,
+    ) -> Override {
+        let until = match self.mode {
+            // 计划不再切换时一直保持，直到手动取消
+            OverrideMode::UntilNextTransition => next_transition,
+            // 配置文件中的值可能超出界面上的范围
+            OverrideMode::ForHours => {
+                let hours = self.hours.clamp(*HOURS.start(), *HOURS.end());
+                Some(now + Duration::hours(hours.into()))
+            }
+            OverrideMode::UntilTomorrow => {
+                Some((now.date() + Duration::days(1)).and_time(NaiveTime::MIN))
+            }
+        };
+        Override { dark, until }
+    }
+}
+
+// 在系统设置等地方修改了主题时的处理方式
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExternalChangePolicy {
+    // 当作手动切换，保持到下一次定时切换
+    #[default]
+    RespectUntilNextTransition,
+    // 立即改回定时计划的模式
+    Reassert,
+    // 关闭该目标的自动切换
+    DisableAutomation,
+}
+
+impl ExternalChangePolicy {
+    pub const ALL: [ExternalChangePolicy; 3] = [
+        ExternalChangePolicy::RespectUntilNextTransition,
+        ExternalChangePolicy::Reassert,
+        ExternalChangePolicy::DisableAutomation,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ExternalChangePolicy::RespectUntilNextTransition => "保持到下一次定时切换",
+            ExternalChangePolicy::Reassert => "立即改回",
+            ExternalChangePolicy::DisableAutomation => "关闭自动切换",
+        }
+    }
+}
+
+// 一次手动切换，until 为 None 时一直保持
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Override {
+    pub dark: bool,
+    #[serde(
+        default,
+        with = "date_time_format",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub until: Option<NaiveDateTime>,
+}
+
+impl Override {
+    pub fn active(&self, now: NaiveDateTime) -> bool {
+        self.until.is_none_or(|until| now < until)
+    }
+}
+
+impl fmt::Display for Override {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = if self.dark {
+            "深色模式"
+        } else {
+            "浅色模式"
+        };
+        match self.until {
+            Some(until) => write!(
+                f,
+                "已手动切换为{}，保持到 {}",
+                mode,
+                until.format("%m-%d %H:%M")
+            ),
+            None => write!(f, "已手动切换为{}，保持到手动取消", mode),
+        }
+    }
+}
+
+mod date_time_format {
+    use chrono::NaiveDateTime;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+    pub fn serialize<S: Serializer>(
+        time: &Option<NaiveDateTime>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match time {
+            Some(time) => serializer.serialize_str(&time.format(FORMAT).to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<NaiveDateTime>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        NaiveDateTime::parse_from_str(&text, FORMAT)
+            .map(Some)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn each_mode_picks_its_end() {
+        let now = at(15, 16, 30);
+        let next = Some(at(15, 18, 0));
+        let start = |mode, hours| OverrideSettings { mode, hours }.start(true, now, next);
+        assert_eq!(
+            start(OverrideMode::UntilNextTransition, 2).until,
+            Some(at(15, 18, 0))
+        );
+        assert_eq!(start(OverrideMode::ForHours, 3).until, Some(at(15, 19, 30)));
+        assert_eq!(
+            start(OverrideMode::ForHours, u32::MAX).until,
+            Some(at(16, 16, 30))
+        );
+        assert_eq!(start(OverrideMode::ForHours, 0).until, Some(at(15, 17, 30)));
+        assert_eq!(
+            start(OverrideMode::UntilTomorrow, 2).until,
+            Some(at(16, 0, 0))
+        );
+        let forever = OverrideSettings::default().start(false, now, None);
+        assert_eq!(forever.until, None);
+        assert!(forever.active(at(31, 0, 0)));
+
+        let until_six = start(OverrideMode::UntilNextTransition, 2);
+        assert!(until_six.active(at(15, 17, 59)));
+        assert!(!until_six.active(at(15, 18, 0)));
+        assert_eq!(
+            until_six.to_string(),
+            "已手动切换为深色模式，保持到 01-15 18:00"
+        );
+    }
+
+    #[test]
+    fn round_trips_through_toml() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Config {
+            app_override: Option<Override>,
+            system_override: Option<Override>,
+        }
+        let config = Config {
+            app_override: Some(Override {
+                dark: true,
+                until: Some(at(15, 18, 0)),
+            }),
+            system_override: Some(Override {
+                dark: false,
+                until: None,
+            }),
+        };
+        let text = toml::to_string(&config).unwrap();
+        assert!(text.contains("until = \"2024-01-15T18:00:00\""), "{}", text);
+        assert_eq!(toml::from_str::<Config>(&text).unwrap(), config);
+    }
+}
@@ -0,0 +1,303 @@
+// 协调层：记录期望的主题和已经生效的主题，只有真正变化时才写入后端；
+// 配置文件也只在内容变化时保存。计数器用于测试和排查问题
+use crate::backend::{Capabilities, ThemeBackend};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ThemeState {
+    pub app: Option<bool>,
+    pub system: Option<bool>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Counters {
+    pub app_writes: u64,
+    pub system_writes: u64,
+    // 请求的状态已经生效，没有写入
+    pub skipped_writes: u64,
+    pub failed_writes: u64,
+    pub config_saves: u64,
+    pub failed_config_saves: u64,
+}
+
+// 写入后端的结果
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Written {
+    // 写入成功的目标
+    pub done: ThemeState,
+    pub failed: u64,
+}
+
+// 把 take_writes 取出的请求写入后端，不需要持有 Reconciler 的锁
+pub fn write(backend: &dyn ThemeBackend, writes: ThemeState) -> Written {
+    let mut written = Written::default();
+    if let Some(dark) = writes.app {
+        match backend.set_dark_mode(dark) {
+            Ok(()) => written.done.app = Some(dark),
+            Err(e) => {
+                eprintln!("Failed to set dark mode: {}", e);
+                written.failed += 1;
+            }
+        }
+    }
+    if let Some(dark) = writes.system {
+        match backend.set_system_dark_mode(dark) {
+            Ok(()) => written.done.system = Some(dark),
+            Err(e) => {
+                eprintln!("Failed to set system dark mode: {}", e);
+                written.failed += 1;
+            }
+        }
+    }
+    written
+}
+
+#[derive(Debug, Default)]
+pub struct Reconciler {
+    // 还没有处理的修改请求
+    pending: ThemeState,
+    // 最近一次读到或写入的后端状态
+    applied: ThemeState,
+    // 不是我们写入的修改(例如在系统设置中切换)，等待处理
+    external: ThemeState,
+    // 正在写入后端的值
+    writing: ThemeState,
+    saved_config: Option<String>,
+    counters: Counters,
+}
+
+impl Reconciler {
+    pub fn counters(&self) -> Counters {
+        self.counters
+    }
+
+    // 从后端读取当前状态，读取失败的目标保持未知。
+    // 和上次写入或读到的值不同时记为外部修改
+    pub fn observe(&mut self, backend: &dyn ThemeBackend) -> ThemeState {
+        let observed = ThemeState {
+            app: backend.is_dark_mode_enabled().ok(),
+            system: backend.is_system_dark_mode_enabled().ok(),
+        };
+        let changed =
+            |before: Option<bool>, now: Option<bool>, writing: Option<bool>| match (before, now) {
+                (Some(before), Some(now)) if before != now && writing != Some(now) => Some(now),
+                _ => None,
+            };
+        if let Some(dark) = changed(self.applied.app, observed.app, self.writing.app) {
+            self.external.app = Some(dark);
+        }
+        // 只有一个配色设置时系统模式随应用模式变化，不单独算作修改
+        if backend.capabilities().separate_system_mode {
+            if let Some(dark) = changed(self.applied.system, observed.system, self.writing.system) {
+                self.external.system = Some(dark);
+            }
+        }
+        self.applied = observed;
+        self.applied
+    }
+
+    // 取出还没有处理的外部修改
+    pub fn take_external_changes(&mut self) -> ThemeState {
+        std::mem::take(&mut self.external)
+    }
+
+    pub fn request_app(&mut self, dark: bool) {
+        self.pending.app = Some(dark);
+    }
+
+    pub fn request_system(&mut self, dark: bool) {
+        self.pending.system = Some(dark);
+    }
+
+    // 把请求写入后端，有写入时返回 true
+    pub fn reconcile(&mut self, backend: &dyn ThemeBackend) -> bool {
+        let writes = self.take_writes(backend.capabilities());
+        let written = write(backend, writes);
+        self.record(written)
+    }
+
+    // 取出需要写入的请求，已经生效的不再写入。
+    // 写入时不用持有锁，写入期间读到的这些值不算外部修改
+    pub fn take_writes(&mut self, capabilities: Capabilities) -> ThemeState {
+        let pending = std::mem::take(&mut self.pending);
+        if !capabilities.write {
+            return ThemeState::default();
+        }
+        let mut skip = |request: Option<bool>, applied: Option<bool>| match request {
+            Some(dark) if applied == Some(dark) => {
+                self.counters.skipped_writes += 1;
+                None
+            }
+            request => request,
+        };
+        let writes = ThemeState {
+            app: skip(pending.app, self.applied.app),
+            // 只有一个配色设置的后端不再单独设置默认windows模式，避免互相覆盖
+            system: skip(
+                pending.system.filter(|_| capabilities.separate_system_mode),
+                self.applied.system,
+            ),
+        };
+        self.writing = writes;
+        writes
+    }
+
+    // 记录 write 的结果，有写入成功时返回 true
+    pub fn record(&mut self, written: Written) -> bool {
+        self.writing = ThemeState::default();
+        if let Some(dark) = written.done.app {
+            self.applied.app = Some(dark);
+            self.counters.app_writes += 1;
+        }
+        if let Some(dark) = written.done.system {
+            self.applied.system = Some(dark);
+            self.counters.system_writes += 1;
+        }
+        self.counters.failed_writes += written.failed;
+        written.done != ThemeState::default()
+    }
+
+    pub fn applied(&self) -> ThemeState {
+        self.applied
+    }
+
+    // 配置内容变化时才写入文件，返回是否写入
+    pub fn persist(&mut self, config: &str, path: &Path) -> Result<bool, Box<dyn Error>> {
+        if self.saved_config.is_none() {
+            // 第一次保存前先看看文件里是不是已经是同样的内容
+            self.saved_config = fs::read_to_string(path).ok();
+        }
+        if self.saved_config.as_deref() == Some(config) {
+            return Ok(false);
+        }
+        // 第一次运行时配置目录可能还不存在
+        let written = match path.parent() {
+            Some(dir) => fs::create_dir_all(dir).and_then(|()| fs::write(path, config)),
+            None => fs::write(path, config),
+        };
+        if let Err(e) = written {
+            self.counters.failed_config_saves += 1;
+            return Err(e.into());
+        }
+        self.saved_config = Some(config.to_owned());
+        self.counters.config_saves += 1;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+
+    #[test]
+    fn writes_only_when_the_state_changes() {
+        let backend = MemoryBackend::default();
+        let mut reconciler = Reconciler::default();
+        reconciler.observe(&backend);
+
+        reconciler.request_app(false);
+        assert!(!reconciler.reconcile(&backend));
+        reconciler.request_app(true);
+        reconciler.request_system(true);
+        assert!(reconciler.reconcile(&backend));
+        // 没有新的请求时什么也不做
+        for _ in 0..100 {
+            reconciler.observe(&backend);
+            assert!(!reconciler.reconcile(&backend));
+        }
+        reconciler.request_app(true);
+        assert!(!reconciler.reconcile(&backend));
+
+        let counters = reconciler.counters();
+        assert_eq!(counters.app_writes, 1);
+        assert_eq!(counters.system_writes, 1);
+        assert_eq!(counters.skipped_writes, 2);
+        assert!(backend.is_dark_mode_enabled().unwrap());
+        assert!(backend.is_system_dark_mode_enabled().unwrap());
+    }
+
+    #[test]
+    fn external_changes_are_not_reverted() {
+        let backend = MemoryBackend::default();
+        let mut reconciler = Reconciler::default();
+        reconciler.request_app(true);
+        reconciler.reconcile(&backend);
+
+        backend.set_dark_mode(false).unwrap();
+        assert_eq!(reconciler.observe(&backend).app, Some(false));
+        assert!(!reconciler.reconcile(&backend));
+        assert!(!backend.is_dark_mode_enabled().unwrap());
+    }
+
+    #[test]
+    fn detects_changes_we_did_not_make() {
+        let backend = MemoryBackend::default();
+        let mut reconciler = Reconciler::default();
+        // 第一次读取没有可比较的值
+        reconciler.observe(&backend);
+        assert_eq!(reconciler.take_external_changes(), ThemeState::default());
+
+        // 自己写入的不算
+        reconciler.request_app(true);
+        reconciler.reconcile(&backend);
+        reconciler.observe(&backend);
+        assert_eq!(reconciler.take_external_changes(), ThemeState::default());
+
+        backend.set_system_dark_mode(true).unwrap();
+        reconciler.observe(&backend);
+        reconciler.observe(&backend);
+        assert_eq!(
+            reconciler.take_external_changes(),
+            ThemeState {
+                app: None,
+                system: Some(true)
+            }
+        );
+        // 取出后不再重复报告
+        reconciler.observe(&backend);
+        assert_eq!(reconciler.take_external_changes(), ThemeState::default());
+    }
+
+    #[test]
+    fn values_being_written_are_not_external_changes() {
+        let backend = MemoryBackend::default();
+        let mut reconciler = Reconciler::default();
+        reconciler.observe(&backend);
+        reconciler.request_app(true);
+        let writes = reconciler.take_writes(backend.capabilities());
+        assert_eq!(writes.app, Some(true));
+        // 不持有锁写入，期间界面读到了新的值
+        let written = write(&backend, writes);
+        reconciler.observe(&backend);
+        assert!(reconciler.record(written));
+        assert_eq!(reconciler.take_external_changes(), ThemeState::default());
+        assert_eq!(reconciler.applied().app, Some(true));
+    }
+
+    #[test]
+    fn saves_config_only_when_it_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "a = 1\n").unwrap();
+        let mut reconciler = Reconciler::default();
+
+        assert!(!reconciler.persist("a = 1\n", &path).unwrap());
+        for _ in 0..10 {
+            reconciler.persist("a = 2\n", &path).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a = 2\n");
+        assert_eq!(reconciler.counters().config_saves, 1);
+    }
+
+    #[test]
+    fn save_errors_are_returned_instead_of_panicking() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut reconciler = Reconciler::default();
+        assert!(reconciler.persist("a = 1\n", dir.path()).is_err());
+        assert_eq!(reconciler.counters().failed_config_saves, 1);
+        assert_eq!(reconciler.counters().config_saves, 0);
+    }
+}
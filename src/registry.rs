@@ -0,0 +1,227 @@
+// 注册表抽象：真实实现只在 Windows 下可用，测试使用内存中的假注册表
+// 路径都相对于 HKEY_CURRENT_USER，错误沿用 winreg 的 io::Error 约定
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegValue {
+    Dword(u32),
+    String(String),
+}
+
+pub trait Registry: fmt::Debug + Send + Sync {
+    // 打开已有的项并读取值，项或值不存在时返回 NotFound
+    fn get_value(&self, path: &str, name: &str) -> io::Result<RegValue>;
+    // 写入已有项的值，项不存在时返回 NotFound
+    fn set_value(&self, path: &str, name: &str, value: &RegValue) -> io::Result<()>;
+    // 创建项（包括中间的项），已存在时什么也不做
+    fn create_key(&self, path: &str) -> io::Result<()>;
+    fn delete_value(&self, path: &str, name: &str) -> io::Result<()>;
+
+    fn get_dword(&self, path: &str, name: &str) -> io::Result<u32> {
+        match self.get_value(path, name)? {
+            RegValue::Dword(value) => Ok(value),
+            RegValue::String(_) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}\\{} is not a DWORD", path, name),
+            )),
+        }
+    }
+}
+
+// 当前用户的注册表
+#[cfg(windows)]
+pub fn current_user() -> io::Result<Arc<dyn Registry>> {
+    Ok(Arc::new(WinRegistry))
+}
+
+#[cfg(not(windows))]
+pub fn current_user() -> io::Result<Arc<dyn Registry>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "the registry is only available on Windows",
+    ))
+}
+
+#[cfg(windows)]
+#[derive(Debug, Default)]
+pub struct WinRegistry;
+
+#[cfg(windows)]
+impl Registry for WinRegistry {
+    fn get_value(&self, path: &str, name: &str) -> io::Result<RegValue> {
+        use winreg::enums::{RegType, HKEY_CURRENT_USER, KEY_READ};
+        let key =
+            winreg::RegKey::predef(HKEY_CURRENT_USER).open_subkey_with_flags(path, KEY_READ)?;
+        let raw = key.get_raw_value(name)?;
+        match raw.vtype {
+            RegType::REG_DWORD => Ok(RegValue::Dword(key.get_value(name)?)),
+            RegType::REG_SZ | RegType::REG_EXPAND_SZ => Ok(RegValue::String(key.get_value(name)?)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported registry value type {:?}", raw.vtype),
+            )),
+        }
+    }
+    fn set_value(&self, path: &str, name: &str, value: &RegValue) -> io::Result<()> {
+        use winreg::enums::{HKEY_CURRENT_USER, KEY_SET_VALUE};
+        let key = winreg::RegKey::predef(HKEY_CURRENT_USER)
+            .open_subkey_with_flags(path, KEY_SET_VALUE)?;
+        match value {
+            RegValue::Dword(value) => key.set_value(name, value),
+            RegValue::String(value) => key.set_value(name, value),
+        }
+    }
+    fn create_key(&self, path: &str) -> io::Result<()> {
+        use winreg::enums::{HKEY_CURRENT_USER, KEY_SET_VALUE};
+        winreg::RegKey::predef(HKEY_CURRENT_USER).create_subkey_with_flags(path, KEY_SET_VALUE)?;
+        Ok(())
+    }
+    fn delete_value(&self, path: &str, name: &str) -> io::Result<()> {
+        use winreg::enums::{HKEY_CURRENT_USER, KEY_SET_VALUE};
+        let key = winreg::RegKey::predef(HKEY_CURRENT_USER)
+            .open_subkey_with_flags(path, KEY_SET_VALUE)?;
+        key.delete_value(name)
+    }
+}
+
+// 内存中的层级注册表，名称不区分大小写，可以把某个项标记为拒绝访问
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MemoryRegistry {
+    root: std::sync::Mutex<MemoryKey>,
+}
+
+#[cfg(test)]
+#[derive(Debug, Default)]
+struct MemoryKey {
+    subkeys: std::collections::BTreeMap<String, MemoryKey>,
+    values: std::collections::BTreeMap<String, RegValue>,
+    denied: bool,
+}
+
+#[cfg(test)]
+impl MemoryRegistry {
+    pub fn deny_access(&self, path: &str) {
+        self.create_key(path).unwrap();
+        let mut root = self.root.lock().unwrap();
+        Self::walk(&mut root, path).unwrap().denied = true;
+    }
+
+    fn walk<'a>(mut key: &'a mut MemoryKey, path: &str) -> io::Result<&'a mut MemoryKey> {
+        for part in path.split('\\').filter(|part| !part.is_empty()) {
+            if key.denied {
+                return Err(io::ErrorKind::PermissionDenied.into());
+            }
+            key = key
+                .subkeys
+                .get_mut(&part.to_lowercase())
+                .ok_or(io::Error::from(io::ErrorKind::NotFound))?;
+        }
+        if key.denied {
+            return Err(io::ErrorKind::PermissionDenied.into());
+        }
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+impl Registry for MemoryRegistry {
+    fn get_value(&self, path: &str, name: &str) -> io::Result<RegValue> {
+        let mut root = self.root.lock().unwrap();
+        let key = Self::walk(&mut root, path)?;
+        key.values
+            .get(&name.to_lowercase())
+            .cloned()
+            .ok_or(io::ErrorKind::NotFound.into())
+    }
+    fn set_value(&self, path: &str, name: &str, value: &RegValue) -> io::Result<()> {
+        let mut root = self.root.lock().unwrap();
+        let key = Self::walk(&mut root, path)?;
+        key.values.insert(name.to_lowercase(), value.clone());
+        Ok(())
+    }
+    fn create_key(&self, path: &str) -> io::Result<()> {
+        let mut root = self.root.lock().unwrap();
+        let mut key = &mut *root;
+        for part in path.split('\\').filter(|part| !part.is_empty()) {
+            if key.denied {
+                return Err(io::ErrorKind::PermissionDenied.into());
+            }
+            key = key.subkeys.entry(part.to_lowercase()).or_default();
+        }
+        Ok(())
+    }
+    fn delete_value(&self, path: &str, name: &str) -> io::Result<()> {
+        let mut root = self.root.lock().unwrap();
+        let key = Self::walk(&mut root, path)?;
+        key.values
+            .remove(&name.to_lowercase())
+            .map(|_| ())
+            .ok_or(io::ErrorKind::NotFound.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_hierarchical_and_case_insensitive() {
+        let registry = MemoryRegistry::default();
+        registry.create_key(r"Software\Test\Inner").unwrap();
+        registry
+            .set_value(r"software\test\inner", "Value", &RegValue::Dword(7))
+            .unwrap();
+        assert_eq!(
+            registry.get_dword(r"SOFTWARE\Test\Inner", "value").unwrap(),
+            7
+        );
+        assert_eq!(
+            registry
+                .get_value(r"Software\Test", "Value")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn set_value_requires_existing_key() {
+        let registry = MemoryRegistry::default();
+        let err = registry
+            .set_value(r"Software\Missing", "Value", &RegValue::Dword(1))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn typed_values() {
+        let registry = MemoryRegistry::default();
+        registry.create_key("Key").unwrap();
+        registry
+            .set_value("Key", "Name", &RegValue::String("text".to_owned()))
+            .unwrap();
+        assert_eq!(
+            registry.get_dword("Key", "Name").unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn denied_keys_and_their_children_fail() {
+        let registry = MemoryRegistry::default();
+        registry.create_key(r"Locked\Child").unwrap();
+        registry.deny_access("Locked");
+        for err in [
+            registry.get_value(r"Locked\Child", "Value").unwrap_err(),
+            registry
+                .set_value("Locked", "Value", &RegValue::Dword(0))
+                .unwrap_err(),
+            registry.create_key(r"Locked\Other").unwrap_err(),
+        ] {
+            assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        }
+    }
+}
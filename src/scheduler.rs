@@ -0,0 +1,687 @@
+// 后台定时线程：计算下一次切换的时间，睡到那时再切换主题，
+// 这样窗口最小化或者没有重绘时也能按时切换
+use crate::backend::ThemeBackend;
+use crate::manual::Override;
+use crate::reconcile::{self, Reconciler};
+use crate::solar::{self, Location, Polar, PolarPolicy, SolarSettings};
+use crate::window::{TimeWindow, WeekWindows, WindowSet};
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+// 最长睡眠时间，防止休眠或修改系统时间后错过切换
+const MAX_SLEEP: std::time::Duration = std::time::Duration::from_secs(60);
+// 写入失败后重试的间隔
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_secs(10);
+
+// 一个目标的定时方式：固定时间，或者按日出日落计算每天的时间
+#[derive(Debug, Clone, PartialEq)]
+pub enum Schedule {
+    Fixed(WeekWindows),
+    Solar {
+        location: Location,
+        settings: SolarSettings,
+        // 固定的深色时间段和特殊日期。特殊日期总是优先，
+        // 固定时间段只在极昼极夜且策略为使用固定时间时使用
+        fallback: WeekWindows,
+    },
+}
+
+impl Schedule {
+    // 某一天的深色时间段，特殊日期优先
+    pub fn window_on(&self, date: NaiveDate) -> WindowSet {
+        match self {
+            Schedule::Fixed(windows) => windows.on(date),
+            Schedule::Solar { fallback, .. } if fallback.exception(date).is_some() => {
+                fallback.on(date)
+            }
+            Schedule::Solar {
+                location,
+                settings,
+                fallback,
+            } => match solar::night_times(date, *location, settings, &Local) {
+                Ok((start, end)) => TimeWindow::new(start, end).into(),
+                Err(polar) => match (settings.polar_policy, polar) {
+                    (PolarPolicy::FixedTimes, _) => fallback.on(date),
+                    (PolarPolicy::FollowSun, Polar::Day) => WindowSet::default(),
+                    (PolarPolicy::FollowSun, Polar::Night) => TimeWindow::FullDay.into(),
+                    (PolarPolicy::AlwaysDark, _) => TimeWindow::FullDay.into(),
+                    (PolarPolicy::AlwaysLight, _) => WindowSet::default(),
+                },
+            },
+        }
+    }
+
+    pub fn is_dark(&self, now: NaiveDateTime) -> bool {
+        self.window_on(now.date()).contains(now.time())
+    }
+
+    // now 之后最近的一次切换时间，今明两天都不切换时返回 None
+    pub fn next_change(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let today = now.date();
+        let tomorrow = today + Duration::days(1);
+        let (today_window, tomorrow_window) = (self.window_on(today), self.window_on(tomorrow));
+        let mut changes: Vec<NaiveDateTime> = today_window
+            .boundaries()
+            .into_iter()
+            .map(|time| today.and_time(time))
+            .chain(
+                tomorrow_window
+                    .boundaries()
+                    .into_iter()
+                    .map(|time| tomorrow.and_time(time)),
+            )
+            .collect();
+        // 两天的时间段不同时，午夜也可能切换
+        let last_moment = NaiveTime::from_hms_nano_opt(23, 59, 59, 999_999_999).unwrap();
+        if today_window.contains(last_moment) != tomorrow_window.contains(NaiveTime::MIN) {
+            changes.push(tomorrow.and_time(NaiveTime::MIN));
+        }
+        changes.into_iter().filter(|time| *time > now).min()
+    }
+}
+
+// 定时计划，None 表示该目标没有启用自动切换
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Plan {
+    pub app: Option<Schedule>,
+    pub system: Option<Schedule>,
+    // 默认windows模式(任务栏)跟随默认应用模式
+    pub system_follows_app: bool,
+    // 手动切换的模式，没有过期时优先于定时计划
+    pub app_override: Option<Override>,
+    pub system_override: Option<Override>,
+}
+
+impl Plan {
+    // 该时刻应用模式和系统模式各自应当是否为深色，None 表示不自动切换
+    pub fn desired(&self, now: NaiveDateTime) -> (Option<bool>, Option<bool>) {
+        let desired = |schedule: &Option<Schedule>, manual: &Option<Override>| {
+            let schedule = schedule.as_ref()?;
+            Some(match manual.filter(|manual| manual.active(now)) {
+                Some(manual) => manual.dark,
+                None => schedule.is_dark(now),
+            })
+        };
+        let app = desired(&self.app, &self.app_override);
+        let system = if self.system_follows_app {
+            app
+        } else {
+            desired(&self.system, &self.system_override)
+        };
+        (app, system)
+    }
+
+    // 下一次切换时间，包括手动切换过期的时间
+    pub fn next_transition(&self, now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let expiries = [self.app_override, self.system_override]
+            .into_iter()
+            .flatten()
+            .filter_map(|manual| manual.until)
+            .filter(|until| *until > now);
+        [&self.app, &self.system]
+            .into_iter()
+            .flatten()
+            .filter_map(|schedule| schedule.next_change(now))
+            .chain(expiries)
+            .min()
+    }
+}
+
+#[derive(Debug)]
+pub struct Scheduler {
+    shared: Arc<Shared>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+#[derive(Debug)]
+struct State {
+    plan: Plan,
+    // 暂停时不按计划切换
+    paused: bool,
+    stop: bool,
+}
+
+impl Scheduler {
+    // 启动定时线程，每次切换之后调用 on_apply，例如用来刷新界面
+    pub fn spawn(
+        backend: Arc<dyn ThemeBackend>,
+        reconciler: Arc<Mutex<Reconciler>>,
+        plan: Plan,
+        on_apply: impl Fn() + Send + 'static,
+    ) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                plan,
+                paused: false,
+                stop: false,
+            }),
+            wake: Condvar::new(),
+        });
+        let thread_shared = shared.clone();
+        let thread =
+            thread::spawn(move || run(&thread_shared, backend.as_ref(), &reconciler, on_apply));
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    // 更新计划，计划有变化时立即唤醒线程重新计算
+    pub fn set_plan(&self, plan: Plan) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.plan != plan {
+            state.plan = plan;
+            self.shared.wake.notify_all();
+        }
+    }
+
+    // 暂停或恢复定时切换，恢复时立即按计划设置一次
+    pub fn set_paused(&self, paused: bool) {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.paused != paused {
+            log::info!("Automation {}", if paused { "paused" } else { "resumed" });
+            state.paused = paused;
+            self.shared.wake.notify_all();
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.shared.state.lock().unwrap().paused
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().stop = true;
+        self.shared.wake.notify_all();
+        // 等定时线程退出，避免退出时正在写入主题
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::error!("Scheduler thread panicked");
+            }
+        }
+    }
+}
+
+fn run(
+    shared: &Shared,
+    backend: &dyn ThemeBackend,
+    reconciler: &Mutex<Reconciler>,
+    on_apply: impl Fn(),
+) {
+    let mut state = shared.state.lock().unwrap();
+    // 只在计划变化或到达切换时间时设置主题，中间手动的修改不会被覆盖
+    let mut last = None;
+    while !state.stop {
+        let now = Local::now().naive_local();
+        let plan = state.plan.clone();
+        // 暂停期间忘记上次的状态，恢复时重新设置
+        let current = (!state.paused).then(|| (plan.clone(), plan.desired(now)));
+        // 设置主题时不持有锁，界面每一帧都要读取状态
+        drop(state);
+        let mut complete = true;
+        if current.is_some() && current != last {
+            let applied = apply(backend, reconciler, &plan, now);
+            if applied.changed {
+                let (app, system) = plan.desired(now);
+                log::info!(
+                    "Applied theme at {}: app dark {:?}, system dark {:?}",
+                    now.format("%Y-%m-%d %H:%M:%S"),
+                    app,
+                    system
+                );
+                on_apply();
+            }
+            complete = applied.complete;
+        }
+        let paused = current.is_none();
+        // 没有全部生效时过一会儿重试，不等到下一次切换
+        if complete {
+            last = current;
+        } else {
+            log::warn!("Theme not fully applied, retrying in {:?}", RETRY_DELAY);
+        }
+        let next = plan.next_transition(now);
+        log::debug!("Next transition: {:?}", next);
+        let limit = if complete { MAX_SLEEP } else { RETRY_DELAY };
+        let sleep = next
+            .and_then(|next| (next - now).to_std().ok())
+            .map_or(limit, |sleep| sleep.min(limit));
+        state = shared.state.lock().unwrap();
+        // 设置期间计划或暂停状态有变化时不再等待
+        if !state.stop && state.plan == plan && state.paused == paused {
+            state = shared.wake.wait_timeout(state, sleep).unwrap().0;
+        }
+    }
+}
+
+// 按计划设置主题的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Applied {
+    // 有写入
+    pub changed: bool,
+    // 计划的模式都已经生效
+    pub complete: bool,
+}
+
+// 按计划设置主题。写入后端时不持有 reconciler 的锁，避免界面等待很慢的后端
+pub fn apply(
+    backend: &dyn ThemeBackend,
+    reconciler: &Mutex<Reconciler>,
+    plan: &Plan,
+    now: NaiveDateTime,
+) -> Applied {
+    let (app, system) = plan.desired(now);
+    let capabilities = backend.capabilities();
+    let mut locked = reconciler.lock().unwrap();
+    locked.observe(backend);
+    // 到了切换时间或计划有变化，之前的外部修改已经过时
+    locked.take_external_changes();
+    if let Some(dark) = app {
+        locked.request_app(dark);
+    }
+    if let Some(dark) = system {
+        locked.request_system(dark);
+    }
+    let writes = locked.take_writes(capabilities);
+    drop(locked);
+    let written = reconcile::write(backend, writes);
+    let mut locked = reconciler.lock().unwrap();
+    let changed = locked.record(written);
+    let applied = locked.applied();
+    // 只读的后端没有办法生效，不用重试
+    let system = system.filter(|_| capabilities.separate_system_mode);
+    let complete = !capabilities.write
+        || (app.is_none_or(|dark| applied.app == Some(dark))
+            && system.is_none_or(|dark| applied.system == Some(dark)));
+    Applied { changed, complete }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryBackend;
+    use chrono::NaiveDate;
+
+    fn at(hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 1, 15)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn window(start: u32, end: u32) -> Schedule {
+        Schedule::Fixed(WeekWindows::every_day([TimeWindow::new(
+            NaiveTime::from_hms_opt(start, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(end, 0, 0).unwrap(),
+        )]))
+    }
+
+    #[test]
+    fn next_change_is_the_closest_boundary() {
+        let night = window(18, 8);
+        assert_eq!(night.next_change(at(12, 0)), Some(at(18, 0)));
+        assert_eq!(
+            night.next_change(at(18, 0)),
+            Some(at(8, 0) + Duration::days(1))
+        );
+        assert_eq!(night.next_change(at(7, 59)), Some(at(8, 0)));
+    }
+
+    #[test]
+    fn daytime_windows_are_dark_only_inside() {
+        let day = window(9, 17);
+        assert!(!day.is_dark(at(8, 59)));
+        assert!(day.is_dark(at(12, 0)));
+        assert!(!day.is_dark(at(20, 0)));
+        assert_eq!(
+            day.next_change(at(17, 0)),
+            Some(at(9, 0) + Duration::days(1))
+        );
+        assert_eq!(
+            Schedule::Fixed(WeekWindows::every_day([TimeWindow::FullDay])).next_change(at(12, 0)),
+            None
+        );
+        assert!(!window(12, 12).is_dark(at(12, 0)));
+    }
+
+    #[test]
+    fn weekday_windows_follow_the_day_they_start() {
+        let hm = |hour| NaiveTime::from_hms_opt(hour, 0, 0).unwrap();
+        let mut week = WeekWindows::every_day([TimeWindow::new(hm(22), hm(7))]);
+        week.set(chrono::Weekday::Fri, [TimeWindow::new(hm(23), hm(10))]);
+        week.set(chrono::Weekday::Sat, [TimeWindow::new(hm(23), hm(10))]);
+        let schedule = Schedule::Fixed(week);
+        // 2024-01-05 是周五
+        let friday = NaiveDate::from_ymd_opt(2024, 1, 5).unwrap();
+        let saturday = friday + Duration::days(1);
+        assert!(!schedule.is_dark(friday.and_time(hm(22))));
+        assert_eq!(
+            schedule.next_change(friday.and_time(hm(22))),
+            Some(friday.and_time(hm(23)))
+        );
+        // 周五晚上的时间段到周六 10 点才结束
+        assert!(schedule.is_dark(saturday.and_time(hm(8))));
+        assert_eq!(
+            schedule.next_change(friday.and_time(hm(23))),
+            Some(saturday.and_time(hm(10)))
+        );
+        // 周日晚上恢复工作日的时间
+        let sunday = saturday + Duration::days(1);
+        assert!(schedule.is_dark(sunday.and_time(hm(9))));
+        assert!(schedule.is_dark(sunday.and_time(hm(22))));
+        let monday = sunday + Duration::days(1);
+        assert!(!schedule.is_dark(monday.and_time(hm(8))));
+    }
+
+    fn polar(policy: PolarPolicy, latitude: f64) -> Schedule {
+        Schedule::Solar {
+            location: Location {
+                latitude,
+                longitude: 18.96,
+            },
+            settings: SolarSettings {
+                enabled: true,
+                polar_policy: policy,
+                ..Default::default()
+            },
+            fallback: WeekWindows::every_day([TimeWindow::new(
+                NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(8, 0, 0).unwrap(),
+            )]),
+        }
+    }
+
+    #[test]
+    fn polar_policy_decides_days_without_sunrise_or_sunset() {
+        // 特罗姆瑟：6 月极昼，12 月极夜
+        let midsummer = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let midwinter = NaiveDate::from_ymd_opt(2024, 12, 21).unwrap();
+        let fixed = polar(PolarPolicy::FixedTimes, 69.65);
+        assert_eq!(
+            fixed.window_on(midsummer),
+            TimeWindow::new(
+                NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(8, 0, 0).unwrap()
+            )
+            .into()
+        );
+        let follow = polar(PolarPolicy::FollowSun, 69.65);
+        assert_eq!(follow.window_on(midsummer), WindowSet::default());
+        assert_eq!(follow.window_on(midwinter), TimeWindow::FullDay.into());
+        let dark = polar(PolarPolicy::AlwaysDark, 69.65);
+        assert_eq!(dark.window_on(midsummer), TimeWindow::FullDay.into());
+        let light = polar(PolarPolicy::AlwaysLight, 69.65);
+        assert_eq!(light.window_on(midwinter), WindowSet::default());
+        // 有日出日落的日子不受影响
+        let equinox = NaiveDate::from_ymd_opt(2024, 3, 20).unwrap();
+        assert_eq!(light.window_on(equinox).boundaries().len(), 2);
+    }
+
+    #[test]
+    fn date_exceptions_override_solar_schedules() {
+        use crate::exceptions::{DateException, ExceptionKind};
+        let midsummer = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let Schedule::Solar {
+            location,
+            settings,
+            mut fallback,
+        } = polar(PolarPolicy::FollowSun, 69.65)
+        else {
+            unreachable!()
+        };
+        fallback.set_exceptions(&[DateException {
+            date: midsummer,
+            kind: ExceptionKind::AlwaysDark,
+            summary: "夏至".to_owned(),
+        }]);
+        let schedule = Schedule::Solar {
+            location,
+            settings,
+            fallback,
+        };
+        assert_eq!(schedule.window_on(midsummer), TimeWindow::FullDay.into());
+        // 其他日期仍然按日出日落计算
+        let next_day = midsummer + Duration::days(1);
+        assert_eq!(schedule.window_on(next_day), WindowSet::default());
+    }
+
+    #[test]
+    fn windows_that_differ_between_days_change_at_midnight() {
+        let light = polar(PolarPolicy::AlwaysLight, 69.65);
+        let midsummer = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        // 连续的极昼不需要切换
+        assert_eq!(
+            light.next_change(midsummer.and_hms_opt(12, 0, 0).unwrap()),
+            None
+        );
+
+        // 找到特罗姆瑟极夜的最后一天
+        let follow = polar(PolarPolicy::FollowSun, 69.65);
+        let mut last_polar = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        while follow.window_on(last_polar + Duration::days(1)) == TimeWindow::FullDay.into() {
+            last_polar += Duration::days(1);
+        }
+        let noon = last_polar.and_hms_opt(12, 0, 0).unwrap();
+        let first_day = last_polar + Duration::days(1);
+        // 跨午夜的夜晚，第二个边界是日出
+        let [_, sunrise] = follow.window_on(first_day).boundaries()[..] else {
+            panic!("{:?}", follow.window_on(first_day));
+        };
+        // 极夜结束后的午夜仍是深色，日出时才切换
+        assert!(follow.is_dark(noon));
+        assert_eq!(follow.next_change(noon), Some(first_day.and_time(sunrise)));
+        // 极夜时保持浅色的话，极夜结束那天的午夜就要变为深色
+        assert!(!light.is_dark(noon));
+        assert_eq!(
+            light.next_change(noon),
+            Some(first_day.and_time(NaiveTime::MIN))
+        );
+    }
+
+    #[test]
+    fn next_transition_considers_both_targets() {
+        let plan = Plan {
+            app: Some(window(18, 8)),
+            system: Some(window(20, 6)),
+            ..Default::default()
+        };
+        assert_eq!(plan.next_transition(at(19, 0)), Some(at(20, 0)));
+        assert_eq!(Plan::default().next_transition(at(19, 0)), None);
+    }
+
+    #[test]
+    fn apply_sets_only_scheduled_targets() {
+        let backend = MemoryBackend::default();
+        let reconciler = Mutex::new(Reconciler::default());
+        let plan = Plan {
+            app: Some(window(18, 8)),
+            ..Default::default()
+        };
+        let changed = Applied {
+            changed: true,
+            complete: true,
+        };
+        assert_eq!(apply(&backend, &reconciler, &plan, at(22, 0)), changed);
+        assert!(backend.is_dark_mode_enabled().unwrap());
+        assert!(!backend.is_system_dark_mode_enabled().unwrap());
+        // 已经是目标状态时不再修改
+        assert!(!apply(&backend, &reconciler, &plan, at(23, 0)).changed);
+
+        let follow = Plan {
+            system_follows_app: true,
+            ..plan
+        };
+        assert_eq!(apply(&backend, &reconciler, &follow, at(23, 0)), changed);
+        assert!(backend.is_system_dark_mode_enabled().unwrap());
+    }
+
+    #[test]
+    fn manual_override_wins_until_it_expires() {
+        let plan = Plan {
+            app: Some(window(18, 8)),
+            app_override: Some(Override {
+                dark: true,
+                until: Some(at(18, 0)),
+            }),
+            // 没有启用自动切换的目标忽略手动切换
+            system_override: Some(Override {
+                dark: true,
+                until: None,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(plan.desired(at(16, 0)), (Some(true), None));
+        // 过期的时间也是一次切换
+        assert_eq!(plan.next_transition(at(16, 0)), Some(at(18, 0)));
+        let light = Plan {
+            app_override: Some(Override {
+                dark: false,
+                until: Some(at(20, 0)),
+            }),
+            ..plan.clone()
+        };
+        assert_eq!(light.desired(at(19, 0)), (Some(false), None));
+        assert_eq!(light.next_transition(at(19, 0)), Some(at(20, 0)));
+        assert_eq!(light.desired(at(20, 0)), (Some(true), None));
+        // 跟随应用模式时也跟随应用模式的手动切换
+        let follow = Plan {
+            system_follows_app: true,
+            ..light
+        };
+        assert_eq!(follow.desired(at(19, 0)), (Some(false), Some(false)));
+    }
+
+    #[test]
+    fn paused_scheduler_applies_again_when_resumed() {
+        let backend = Arc::new(MemoryBackend::default());
+        let reconciler = Arc::new(Mutex::new(Reconciler::default()));
+        let scheduler = Scheduler::spawn(backend.clone(), reconciler, Plan::default(), || {});
+        scheduler.set_paused(true);
+        assert!(scheduler.paused());
+        scheduler.set_plan(Plan {
+            app: Some(Schedule::Fixed(WeekWindows::every_day([
+                TimeWindow::FullDay,
+            ]))),
+            ..Default::default()
+        });
+        thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!backend.is_dark_mode_enabled().unwrap());
+
+        scheduler.set_paused(false);
+        for _ in 0..50 {
+            if backend.is_dark_mode_enabled().unwrap() {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(20));
+        }
+        assert!(backend.is_dark_mode_enabled().unwrap());
+    }
+
+    // 写入很慢的后端，开始写入时发出通知
+    #[derive(Debug)]
+    struct SlowBackend {
+        inner: MemoryBackend,
+        started: Mutex<std::sync::mpsc::Sender<()>>,
+    }
+
+    impl ThemeBackend for SlowBackend {
+        fn name(&self) -> &'static str {
+            "slow"
+        }
+        fn capabilities(&self) -> crate::backend::Capabilities {
+            self.inner.capabilities()
+        }
+        fn is_dark_mode_enabled(&self) -> Result<bool, Box<dyn std::error::Error>> {
+            self.inner.is_dark_mode_enabled()
+        }
+        fn set_dark_mode(&self, enabled: bool) -> Result<(), Box<dyn std::error::Error>> {
+            let _ = self.started.lock().unwrap().send(());
+            thread::sleep(std::time::Duration::from_millis(500));
+            self.inner.set_dark_mode(enabled)
+        }
+        fn is_system_dark_mode_enabled(&self) -> Result<bool, Box<dyn std::error::Error>> {
+            self.inner.is_system_dark_mode_enabled()
+        }
+        fn set_system_dark_mode(&self, enabled: bool) -> Result<(), Box<dyn std::error::Error>> {
+            self.inner.set_system_dark_mode(enabled)
+        }
+    }
+
+    #[test]
+    fn state_is_not_locked_while_applying() {
+        let (sender, started) = std::sync::mpsc::channel();
+        let backend = Arc::new(SlowBackend {
+            inner: MemoryBackend::default(),
+            started: Mutex::new(sender),
+        });
+        let reconciler = Arc::new(Mutex::new(Reconciler::default()));
+        let plan = Plan {
+            app: Some(Schedule::Fixed(WeekWindows::every_day([
+                TimeWindow::FullDay,
+            ]))),
+            ..Default::default()
+        };
+        let scheduler = Scheduler::spawn(backend, reconciler.clone(), plan, || {});
+        started.recv().unwrap();
+        let begin = std::time::Instant::now();
+        assert!(!scheduler.paused());
+        scheduler.set_paused(true);
+        // 界面每一帧都会锁定 reconciler
+        assert!(reconciler.try_lock().is_ok());
+        assert!(begin.elapsed() < std::time::Duration::from_millis(250));
+    }
+
+    // 读取正常、写入总是失败的后端
+    #[derive(Debug, Default)]
+    struct FailingBackend(MemoryBackend);
+
+    impl ThemeBackend for FailingBackend {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+        fn capabilities(&self) -> crate::backend::Capabilities {
+            self.0.capabilities()
+        }
+        fn is_dark_mode_enabled(&self) -> Result<bool, Box<dyn std::error::Error>> {
+            self.0.is_dark_mode_enabled()
+        }
+        fn set_dark_mode(&self, _enabled: bool) -> Result<(), Box<dyn std::error::Error>> {
+            Err("busy".into())
+        }
+        fn is_system_dark_mode_enabled(&self) -> Result<bool, Box<dyn std::error::Error>> {
+            self.0.is_system_dark_mode_enabled()
+        }
+        fn set_system_dark_mode(&self, _enabled: bool) -> Result<(), Box<dyn std::error::Error>> {
+            Err("busy".into())
+        }
+    }
+
+    #[test]
+    fn failed_writes_are_not_complete() {
+        let reconciler = Mutex::new(Reconciler::default());
+        let plan = Plan {
+            app: Some(window(18, 8)),
+            ..Default::default()
+        };
+        let applied = apply(&FailingBackend::default(), &reconciler, &plan, at(22, 0));
+        assert_eq!(
+            applied,
+            Applied {
+                changed: false,
+                complete: false
+            }
+        );
+        assert_eq!(reconciler.lock().unwrap().counters().failed_writes, 1);
+        // 写入成功后才算完成
+        let applied = apply(&MemoryBackend::default(), &reconciler, &plan, at(22, 0));
+        assert!(applied.changed && applied.complete);
+    }
+}
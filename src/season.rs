@@ -0,0 +1,232 @@
+// 每年重复的日期范围，用于按季节设置不同的深色时间段
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+// 不带年份的日期，配置文件中写作 "MM-DD"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MonthDay {
+    month: u32,
+    day: u32,
+}
+
+impl MonthDay {
+    // 按闰年检查，允许 02-29
+    pub fn new(month: u32, day: u32) -> Option<Self> {
+        NaiveDate::from_ymd_opt(2024, month, day).map(|_| MonthDay { month, day })
+    }
+
+    pub fn of(date: NaiveDate) -> Self {
+        MonthDay {
+            month: date.month(),
+            day: date.day(),
+        }
+    }
+
+    pub fn month(&self) -> u32 {
+        self.month
+    }
+
+    pub fn day(&self) -> u32 {
+        self.day
+    }
+}
+
+impl fmt::Display for MonthDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}-{:02}", self.month, self.day)
+    }
+}
+
+impl Serialize for MonthDay {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for MonthDay {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.split_once('-')
+            .and_then(|(month, day)| MonthDay::new(month.parse().ok()?, day.parse().ok()?))
+            .ok_or_else(|| serde::de::Error::custom(format!("无效的日期 {}，应为 MM-DD", text)))
+    }
+}
+
+// 包含首尾两天，from 晚于 to 时跨过新年，例如 10-01 至 03-31
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateRange {
+    pub from: MonthDay,
+    pub to: MonthDay,
+}
+
+impl DateRange {
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        let day = MonthDay::of(date);
+        if self.from <= self.to {
+            self.from <= day && day <= self.to
+        } else {
+            day >= self.from || day <= self.to
+        }
+    }
+}
+
+impl fmt::Display for DateRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} 至 {}", self.from, self.to)
+    }
+}
+
+// 包含这一天的日期范围。02-29 不在任何范围内时按 02-28 算，
+// 这样写到 02-28、从 03-01 开始的季节在闰年也不会漏掉一天
+fn matching(ranges: &[DateRange], date: NaiveDate) -> Vec<usize> {
+    let matching = |date| {
+        (0..ranges.len())
+            .filter(|&i| ranges[i].contains(date))
+            .collect()
+    };
+    let found: Vec<usize> = matching(date);
+    match date.pred_opt() {
+        Some(before) if found.is_empty() && date.month() == 2 && date.day() == 29 => {
+            matching(before)
+        }
+        _ => found,
+    }
+}
+
+// 某天属于第几个日期范围
+pub fn position(ranges: &[DateRange], date: NaiveDate) -> Option<usize> {
+    matching(ranges, date).first().copied()
+}
+
+// 检查日期范围是否正好覆盖全年：没有遗漏的日期，也没有同时属于两个范围的日期
+pub fn validate_coverage(ranges: &[DateRange]) -> Result<(), String> {
+    let mut overlap = None;
+    let mut gaps: Vec<(NaiveDate, NaiveDate)> = Vec::new();
+    // 闰年包含所有可能的日期
+    let year = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap().iter_days();
+    for date in year.take_while(|date| date.year() == 2024) {
+        match matching(ranges, date)[..] {
+            [] => match gaps.last_mut() {
+                Some((_, end)) if end.succ_opt() == Some(date) => *end = date,
+                _ => gaps.push((date, date)),
+            },
+            [a, b, ..] if overlap.is_none() => overlap = Some((date, a, b)),
+            _ => {}
+        }
+    }
+    let mut problems: Vec<String> = gaps
+        .into_iter()
+        .map(|(from, to)| {
+            let range = DateRange {
+                from: MonthDay::of(from),
+                to: MonthDay::of(to),
+            };
+            format!("{} 没有对应的季节", range)
+        })
+        .collect();
+    if let Some((date, a, b)) = overlap {
+        problems.push(format!(
+            "{} 同时属于第 {} 和第 {} 个季节，季节之间不能重叠",
+            MonthDay::of(date),
+            a + 1,
+            b + 1
+        ));
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems.join("；"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(from: (u32, u32), to: (u32, u32)) -> DateRange {
+        DateRange {
+            from: MonthDay::new(from.0, from.1).unwrap(),
+            to: MonthDay::new(to.0, to.1).unwrap(),
+        }
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    #[test]
+    fn ranges_may_wrap_around_new_year() {
+        let summer = range((4, 1), (9, 30));
+        assert!(summer.contains(date(2025, 4, 1)));
+        assert!(summer.contains(date(2025, 9, 30)));
+        assert!(!summer.contains(date(2025, 10, 1)));
+        let winter = range((10, 1), (3, 31));
+        assert!(winter.contains(date(2025, 12, 31)));
+        assert!(winter.contains(date(2026, 1, 1)));
+        assert!(winter.contains(date(2024, 2, 29)));
+        assert!(!winter.contains(date(2025, 4, 1)));
+    }
+
+    #[test]
+    fn summer_and_winter_cover_the_year() {
+        assert_eq!(
+            validate_coverage(&[range((4, 1), (9, 30)), range((10, 1), (3, 31))]),
+            Ok(())
+        );
+        assert_eq!(validate_coverage(&[range((1, 1), (12, 31))]), Ok(()));
+    }
+
+    #[test]
+    fn leap_day_belongs_to_the_season_ending_on_february_28() {
+        let seasons = [range((3, 1), (9, 30)), range((10, 1), (2, 28))];
+        assert_eq!(validate_coverage(&seasons), Ok(()));
+        let date = |year, month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap();
+        assert_eq!(position(&seasons, date(2024, 2, 29)), Some(1));
+        assert_eq!(position(&seasons, date(2024, 3, 1)), Some(0));
+        assert_eq!(position(&seasons, date(2025, 2, 28)), Some(1));
+        // 明确写了 02-29 的季节优先
+        let seasons = [range((2, 29), (9, 30)), range((10, 1), (2, 28))];
+        assert_eq!(validate_coverage(&seasons), Ok(()));
+        assert_eq!(position(&seasons, date(2024, 2, 29)), Some(0));
+    }
+
+    #[test]
+    fn gaps_and_overlaps_are_reported() {
+        let error =
+            validate_coverage(&[range((4, 1), (9, 30)), range((11, 1), (2, 28))]).unwrap_err();
+        assert_eq!(
+            error,
+            "03-01 至 03-31 没有对应的季节；10-01 至 10-31 没有对应的季节"
+        );
+        let error =
+            validate_coverage(&[range((1, 1), (6, 30)), range((6, 1), (12, 31))]).unwrap_err();
+        assert_eq!(error, "06-01 同时属于第 1 和第 2 个季节，季节之间不能重叠");
+        assert_eq!(
+            validate_coverage(&[]).unwrap_err(),
+            "01-01 至 12-31 没有对应的季节"
+        );
+        // 缺少年底的几天
+        assert_eq!(
+            validate_coverage(&[range((1, 1), (12, 29))]).unwrap_err(),
+            "12-30 至 12-31 没有对应的季节"
+        );
+    }
+
+    #[test]
+    fn month_day_serialization() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Wrapper {
+            day: MonthDay,
+        }
+        let wrapper = Wrapper {
+            day: MonthDay::new(2, 29).unwrap(),
+        };
+        let text = toml::to_string(&wrapper).unwrap();
+        assert_eq!(text.trim(), "day = \"02-29\"");
+        assert_eq!(toml::from_str::<Wrapper>(&text).unwrap(), wrapper);
+        assert!(toml::from_str::<Wrapper>("day = \"02-30\"").is_err());
+        assert!(toml::from_str::<Wrapper>("day = \"summer\"").is_err());
+        assert_eq!(MonthDay::new(13, 1), None);
+    }
+}
@@ -0,0 +1,336 @@
+// 日出日落时间的离线计算，使用简化的日出方程(与 NOAA 算法一致)，精度约一分钟
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+// 官方日出日落：太阳上边缘与地平线相切，已考虑大气折射
+pub const SUNRISE_ELEVATION: f64 = -0.833;
+// 2000-01-01 12:00 UTC 的儒略日
+const J2000: f64 = 2451545.0;
+const UNIX_EPOCH_JULIAN_DAY: f64 = 2440587.5;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Location {
+    // 北纬为正
+    pub latitude: f64,
+    // 东经为正
+    pub longitude: f64,
+}
+
+// 触发切换的太阳事件，对应不同的太阳高度角
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SolarEvent {
+    // 日出日落
+    #[default]
+    Official,
+    // 民用晨昏蒙影，太阳在地平线下 6 度
+    Civil,
+    // 航海晨昏蒙影，地平线下 12 度
+    Nautical,
+    // 天文晨昏蒙影，地平线下 18 度
+    Astronomical,
+    // 使用 custom_elevation 指定的高度角
+    Custom,
+}
+
+impl SolarEvent {
+    pub const ALL: [SolarEvent; 5] = [
+        SolarEvent::Official,
+        SolarEvent::Civil,
+        SolarEvent::Nautical,
+        SolarEvent::Astronomical,
+        SolarEvent::Custom,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            SolarEvent::Official => "日出日落",
+            SolarEvent::Civil => "民用晨昏蒙影",
+            SolarEvent::Nautical => "航海晨昏蒙影",
+            SolarEvent::Astronomical => "天文晨昏蒙影",
+            SolarEvent::Custom => "自定义太阳高度角",
+        }
+    }
+}
+
+// 太阳整天不升起或不落下(极昼、极夜)时的处理方式
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PolarPolicy {
+    // 使用设定的固定时间
+    #[default]
+    FixedTimes,
+    // 极昼时浅色，极夜时深色
+    FollowSun,
+    AlwaysDark,
+    AlwaysLight,
+}
+
+impl PolarPolicy {
+    pub const ALL: [PolarPolicy; 4] = [
+        PolarPolicy::FixedTimes,
+        PolarPolicy::FollowSun,
+        PolarPolicy::AlwaysDark,
+        PolarPolicy::AlwaysLight,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            PolarPolicy::FixedTimes => "使用固定时间",
+            PolarPolicy::FollowSun => "极昼浅色、极夜深色",
+            PolarPolicy::AlwaysDark => "保持深色模式",
+            PolarPolicy::AlwaysLight => "保持浅色模式",
+        }
+    }
+}
+
+// 每个目标各自的日出日落设置
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SolarSettings {
+    pub enabled: bool,
+    pub event: SolarEvent,
+    // 太阳高度角(度)，负数表示在地平线以下，只在 event 为 custom 时使用
+    pub custom_elevation: f64,
+    // 正数表示推迟，负数表示提前
+    pub sunset_offset_minutes: i32,
+    pub sunrise_offset_minutes: i32,
+    pub polar_policy: PolarPolicy,
+}
+
+impl SolarSettings {
+    pub fn elevation(&self) -> f64 {
+        match self.event {
+            SolarEvent::Official => SUNRISE_ELEVATION,
+            SolarEvent::Civil => -6.0,
+            SolarEvent::Nautical => -12.0,
+            SolarEvent::Astronomical => -18.0,
+            SolarEvent::Custom => self.custom_elevation,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SunTimes {
+    Normal {
+        sunrise: DateTime<Utc>,
+        sunset: DateTime<Utc>,
+    },
+    // 太阳整天都在该高度之上(极昼)或之下(极夜)
+    AlwaysAbove,
+    AlwaysBelow,
+}
+
+// 计算某天太阳升到/落到 elevation 度的时间
+pub fn sun_times(date: NaiveDate, location: Location, elevation: f64) -> SunTimes {
+    let epoch = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap();
+    let n = (date - epoch).num_days() as f64;
+    // 平太阳正午
+    let mean_noon = n - location.longitude / 360.0;
+    let anomaly = (357.5291 + 0.98560028 * mean_noon)
+        .rem_euclid(360.0)
+        .to_radians();
+    let center =
+        1.9148 * anomaly.sin() + 0.0200 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+    let ecliptic_longitude = (anomaly.to_degrees() + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+    let transit =
+        J2000 + mean_noon + 0.0053 * anomaly.sin() - 0.0069 * (2.0 * ecliptic_longitude).sin();
+    let declination = (ecliptic_longitude.sin() * 23.4397f64.to_radians().sin()).asin();
+
+    let latitude = location.latitude.to_radians();
+    let cos_hour_angle = (elevation.to_radians().sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if cos_hour_angle < -1.0 {
+        return SunTimes::AlwaysAbove;
+    }
+    if cos_hour_angle > 1.0 || cos_hour_angle.is_nan() {
+        return SunTimes::AlwaysBelow;
+    }
+    let hour_angle = cos_hour_angle.acos().to_degrees();
+    SunTimes::Normal {
+        sunrise: julian_to_utc(transit - hour_angle / 360.0),
+        sunset: julian_to_utc(transit + hour_angle / 360.0),
+    }
+}
+
+fn julian_to_utc(julian_day: f64) -> DateTime<Utc> {
+    let millis = ((julian_day - UNIX_EPOCH_JULIAN_DAY) * 86_400_000.0).round() as i64;
+    Utc.timestamp_millis_opt(millis).unwrap()
+}
+
+// 太阳整天在设定高度之上或之下
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polar {
+    Day,
+    Night,
+}
+
+// 某天在时区 tz 中深色模式开始(日落)和结束(日出)的时间，已加上偏移。
+// 偏移后跨过午夜的时间会被限制在当天之内
+pub fn night_times<Tz: TimeZone>(
+    date: NaiveDate,
+    location: Location,
+    settings: &SolarSettings,
+    tz: &Tz,
+) -> Result<(NaiveTime, NaiveTime), Polar> {
+    let (sunrise, sunset) = match sun_times(date, location, settings.elevation()) {
+        SunTimes::Normal { sunrise, sunset } => (sunrise, sunset),
+        SunTimes::AlwaysAbove => return Err(Polar::Day),
+        SunTimes::AlwaysBelow => return Err(Polar::Night),
+    };
+    let local = |time: DateTime<Utc>, offset: i32| {
+        let local = time.with_timezone(tz).naive_local() + Duration::minutes(offset as i64);
+        let day_start = date.and_hms_opt(0, 0, 0).unwrap();
+        let day_end = date.and_hms_opt(23, 59, 59).unwrap();
+        local.clamp(day_start, day_end).time()
+    };
+    Ok((
+        local(sunset, settings.sunset_offset_minutes),
+        local(sunrise, settings.sunrise_offset_minutes),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    fn assert_close(actual: DateTime<Utc>, expected: &str) {
+        let expected = DateTime::parse_from_rfc3339(expected).unwrap();
+        let diff = (actual - expected.with_timezone(&Utc)).num_seconds().abs();
+        assert!(diff <= 120, "{} is not close to {}", actual, expected);
+    }
+
+    const LONDON: Location = Location {
+        latitude: 51.5074,
+        longitude: -0.1278,
+    };
+    const NEW_YORK: Location = Location {
+        latitude: 40.7128,
+        longitude: -74.0060,
+    };
+    const SYDNEY: Location = Location {
+        latitude: -33.8688,
+        longitude: 151.2093,
+    };
+
+    fn event_times(
+        location: Location,
+        date: (i32, u32, u32),
+        event: SolarEvent,
+    ) -> (DateTime<Utc>, DateTime<Utc>) {
+        let settings = SolarSettings {
+            event,
+            ..Default::default()
+        };
+        let date = NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap();
+        match sun_times(date, location, settings.elevation()) {
+            SunTimes::Normal { sunrise, sunset } => (sunrise, sunset),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    // 晨昏蒙影开始/结束时间，与天文年历的数值比较，误差不超过两分钟
+    #[test]
+    fn twilight_matches_almanac_values() {
+        #[rustfmt::skip]
+        let cases = [
+            (LONDON, (2024, 12, 21), SolarEvent::Official, "2024-12-21T08:04:00Z", "2024-12-21T15:54:00Z"),
+            (LONDON, (2024, 12, 21), SolarEvent::Civil, "2024-12-21T07:24:00Z", "2024-12-21T16:34:00Z"),
+            (LONDON, (2024, 12, 21), SolarEvent::Nautical, "2024-12-21T06:40:00Z", "2024-12-21T17:17:00Z"),
+            (LONDON, (2024, 12, 21), SolarEvent::Astronomical, "2024-12-21T06:00:00Z", "2024-12-21T17:58:00Z"),
+            (LONDON, (2024, 6, 21), SolarEvent::Official, "2024-06-21T04:43:00+01:00", "2024-06-21T21:22:00+01:00"),
+            (LONDON, (2024, 6, 21), SolarEvent::Civil, "2024-06-21T03:55:00+01:00", "2024-06-21T22:09:00+01:00"),
+            (LONDON, (2024, 6, 21), SolarEvent::Nautical, "2024-06-21T02:41:00+01:00", "2024-06-21T23:24:00+01:00"),
+            (NEW_YORK, (2024, 1, 15), SolarEvent::Official, "2024-01-15T07:18:00-05:00", "2024-01-15T16:53:00-05:00"),
+            (NEW_YORK, (2024, 1, 15), SolarEvent::Civil, "2024-01-15T06:48:00-05:00", "2024-01-15T17:23:00-05:00"),
+            (NEW_YORK, (2024, 1, 15), SolarEvent::Nautical, "2024-01-15T06:14:00-05:00", "2024-01-15T17:57:00-05:00"),
+            (NEW_YORK, (2024, 1, 15), SolarEvent::Astronomical, "2024-01-15T05:41:00-05:00", "2024-01-15T18:30:00-05:00"),
+            (SYDNEY, (2024, 1, 15), SolarEvent::Official, "2024-01-15T05:59:00+11:00", "2024-01-15T20:09:00+11:00"),
+            (SYDNEY, (2024, 1, 15), SolarEvent::Civil, "2024-01-15T05:31:00+11:00", "2024-01-15T20:37:00+11:00"),
+            (SYDNEY, (2024, 1, 15), SolarEvent::Astronomical, "2024-01-15T04:19:00+11:00", "2024-01-15T21:49:00+11:00"),
+        ];
+        for (location, date, event, dawn, dusk) in cases {
+            let (rise, set) = event_times(location, date, event);
+            assert_close(rise, dawn);
+            assert_close(set, dusk);
+        }
+    }
+
+    #[test]
+    fn custom_elevation_is_used_for_custom_event() {
+        let settings = SolarSettings {
+            event: SolarEvent::Custom,
+            custom_elevation: -6.0,
+            ..Default::default()
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 1, 15).unwrap();
+        let custom = sun_times(date, NEW_YORK, settings.elevation());
+        let civil = sun_times(date, NEW_YORK, -6.0);
+        assert_eq!(custom, civil);
+    }
+
+    #[test]
+    fn astronomical_twilight_lasts_all_night_in_london_summer() {
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        assert_eq!(sun_times(date, LONDON, -18.0), SunTimes::AlwaysAbove);
+    }
+
+    #[test]
+    fn sunrise_and_sunset_in_beijing() {
+        let beijing = Location {
+            latitude: 39.9042,
+            longitude: 116.4074,
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let SunTimes::Normal { sunrise, sunset } = sun_times(date, beijing, SUNRISE_ELEVATION)
+        else {
+            panic!("the sun rises in Beijing");
+        };
+        // 北京 2024-06-21 日出 04:46，日落 19:46 (UTC+8)
+        assert_close(sunrise, "2024-06-21T04:46:00+08:00");
+        assert_close(sunset, "2024-06-21T19:46:00+08:00");
+    }
+
+    #[test]
+    fn night_times_apply_offsets_in_local_time() {
+        let beijing = Location {
+            latitude: 39.9042,
+            longitude: 116.4074,
+        };
+        let settings = SolarSettings {
+            enabled: true,
+            sunset_offset_minutes: -30,
+            sunrise_offset_minutes: 45,
+            ..Default::default()
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        let (start, end) = night_times(date, beijing, &settings, &tz).unwrap();
+        let minutes = |time: NaiveTime| (time - NaiveTime::MIN).num_minutes();
+        assert!((minutes(start) - (19 * 60 + 16)).abs() <= 2, "{}", start);
+        assert!((minutes(end) - (5 * 60 + 31)).abs() <= 2, "{}", end);
+    }
+
+    #[test]
+    fn offsets_do_not_cross_midnight() {
+        let beijing = Location {
+            latitude: 39.9042,
+            longitude: 116.4074,
+        };
+        let settings = SolarSettings {
+            enabled: true,
+            sunset_offset_minutes: 600,
+            sunrise_offset_minutes: -600,
+            ..Default::default()
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let tz = FixedOffset::east_opt(8 * 3600).unwrap();
+        let (start, end) = night_times(date, beijing, &settings, &tz).unwrap();
+        assert_eq!(start, NaiveTime::from_hms_opt(23, 59, 59).unwrap());
+        assert_eq!(end, NaiveTime::MIN);
+    }
+}
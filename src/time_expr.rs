@@ -0,0 +1,606 @@
+// 时间表达式，例如 max(sunset - 30m, 18:00)、min(sunrise, 07:30)，按日期和位置计算出当天的时间
+use crate::solar::{sun_times, Location, SunTimes, SUNRISE_ELEVATION};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SunEvent {
+    Sunrise,
+    Sunset,
+    // 民用晨昏蒙影的开始和结束，太阳在地平线下 6 度
+    Dawn,
+    Dusk,
+}
+
+impl SunEvent {
+    const ALL: [SunEvent; 4] = [
+        SunEvent::Sunrise,
+        SunEvent::Sunset,
+        SunEvent::Dawn,
+        SunEvent::Dusk,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            SunEvent::Sunrise => "sunrise",
+            SunEvent::Sunset => "sunset",
+            SunEvent::Dawn => "dawn",
+            SunEvent::Dusk => "dusk",
+        }
+    }
+
+    fn time(&self, date: NaiveDate, location: Location) -> Option<DateTime<Utc>> {
+        let elevation = match self {
+            SunEvent::Sunrise | SunEvent::Sunset => SUNRISE_ELEVATION,
+            SunEvent::Dawn | SunEvent::Dusk => -6.0,
+        };
+        match (sun_times(date, location, elevation), self) {
+            (SunTimes::Normal { sunrise, .. }, SunEvent::Sunrise | SunEvent::Dawn) => Some(sunrise),
+            (SunTimes::Normal { sunset, .. }, SunEvent::Sunset | SunEvent::Dusk) => Some(sunset),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimeExpr {
+    Time(NaiveTime),
+    Sun(SunEvent),
+    // 正数表示推迟，负数表示提前
+    Offset(Box<TimeExpr>, Duration),
+    Min(Vec<TimeExpr>),
+    Max(Vec<TimeExpr>),
+}
+
+impl Default for TimeExpr {
+    fn default() -> Self {
+        TimeExpr::Time(NaiveTime::MIN)
+    }
+}
+
+impl TimeExpr {
+    // 固定的时间，表达式中用到太阳事件时返回 None
+    pub fn as_time(&self) -> Option<NaiveTime> {
+        match self {
+            TimeExpr::Time(time) => Some(*time),
+            _ => None,
+        }
+    }
+
+    // 某天在时区 tz 中的时间(精确到秒)，超出当天的结果限制在当天之内。
+    // 没有日出日落(极昼、极夜)时太阳事件没有值，min/max 只比较有值的参数，
+    // 全部没有值时返回 None
+    pub fn eval<Tz: TimeZone>(
+        &self,
+        date: NaiveDate,
+        location: Location,
+        tz: &Tz,
+    ) -> Option<NaiveTime> {
+        let day_start = date.and_time(NaiveTime::MIN);
+        let day_end = date.and_hms_opt(23, 59, 59).unwrap();
+        self.eval_at(date, location, tz)
+            .and_then(|time| time.clamp(day_start, day_end).time().with_nanosecond(0))
+    }
+
+    fn eval_at<Tz: TimeZone>(
+        &self,
+        date: NaiveDate,
+        location: Location,
+        tz: &Tz,
+    ) -> Option<NaiveDateTime> {
+        let each = |exprs: &[TimeExpr]| -> Vec<NaiveDateTime> {
+            exprs
+                .iter()
+                .filter_map(|expr| expr.eval_at(date, location, tz))
+                .collect()
+        };
+        match self {
+            TimeExpr::Time(time) => Some(date.and_time(*time)),
+            TimeExpr::Sun(event) => event
+                .time(date, location)
+                .map(|time| time.with_timezone(tz).naive_local()),
+            TimeExpr::Offset(expr, offset) => expr
+                .eval_at(date, location, tz)
+                .and_then(|time| time.checked_add_signed(*offset)),
+            TimeExpr::Min(exprs) => each(exprs).into_iter().min(),
+            TimeExpr::Max(exprs) => each(exprs).into_iter().max(),
+        }
+    }
+}
+
+impl From<NaiveTime> for TimeExpr {
+    fn from(time: NaiveTime) -> Self {
+        TimeExpr::Time(time)
+    }
+}
+
+impl fmt::Display for TimeExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let list = |exprs: &[TimeExpr]| {
+            exprs
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        match self {
+            TimeExpr::Time(time) => write!(f, "{}", time.format("%H:%M:%S")),
+            TimeExpr::Sun(event) => write!(f, "{}", event.name()),
+            TimeExpr::Offset(expr, offset) if *offset < Duration::zero() => {
+                write!(f, "{} - {}", expr, format_duration(-*offset))
+            }
+            TimeExpr::Offset(expr, offset) => write!(f, "{} + {}", expr, format_duration(*offset)),
+            TimeExpr::Min(exprs) => write!(f, "min({})", list(exprs)),
+            TimeExpr::Max(exprs) => write!(f, "max({})", list(exprs)),
+        }
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.num_seconds();
+    let parts = [
+        (seconds / 3600, "h"),
+        (seconds / 60 % 60, "m"),
+        (seconds % 60, "s"),
+    ];
+    let text: String = parts
+        .iter()
+        .filter(|(value, _)| *value != 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+    if text.is_empty() {
+        "0m".to_owned()
+    } else {
+        text
+    }
+}
+
+// 解析错误，position 是出错的字符位置(从 1 开始)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "第 {} 个字符：{}", self.position, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl FromStr for TimeExpr {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Self, ParseError> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser {
+            tokens,
+            next: 0,
+            end: text.chars().count() + 1,
+        };
+        if parser.tokens.is_empty() {
+            return Err(parser.error_at(1, "表达式为空"));
+        }
+        let expr = parser.expr()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some((position, token)) => {
+                Err(parser.error_at(position, &format!("多余的内容 {}", token)))
+            }
+        }
+    }
+}
+
+impl Serialize for TimeExpr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeExpr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(|e: ParseError| {
+            serde::de::Error::custom(format!("无效的时间表达式 {:?}，{}", text, e))
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Time(NaiveTime),
+    Duration(Duration),
+    Name(String),
+    Open,
+    Close,
+    Comma,
+    Plus,
+    Minus,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Time(time) => write!(f, "{}", time.format("%H:%M:%S")),
+            Token::Duration(duration) => write!(f, "{}", format_duration(*duration)),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+            Token::Plus => write!(f, "'+'"),
+            Token::Minus => write!(f, "'-'"),
+        }
+    }
+}
+
+// 切分成记号，每个记号带上开始的字符位置
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let chars: Vec<char> = text.chars().collect();
+    let error = |position: usize, message: String| ParseError { position, message };
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let position = i + 1;
+        let c = chars[i];
+        let punctuation = match c {
+            '(' => Some(Token::Open),
+            ')' => Some(Token::Close),
+            ',' => Some(Token::Comma),
+            '+' => Some(Token::Plus),
+            '-' => Some(Token::Minus),
+            _ => None,
+        };
+        if c.is_whitespace() {
+            i += 1;
+        } else if let Some(token) = punctuation {
+            tokens.push((position, token));
+            i += 1;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            tokens.push((position, Token::Name(name.to_ascii_lowercase())));
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == ':') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            let token = if word.contains(':') {
+                NaiveTime::parse_from_str(&word, "%H:%M:%S")
+                    .or_else(|_| NaiveTime::parse_from_str(&word, "%H:%M"))
+                    .map(Token::Time)
+                    .map_err(|_| {
+                        error(
+                            position,
+                            format!("无效的时间 {}，应为 HH:MM 或 HH:MM:SS", word),
+                        )
+                    })?
+            } else {
+                Token::Duration(parse_duration(&word).map_err(|message| error(position, message))?)
+            };
+            tokens.push((position, token));
+        } else {
+            return Err(error(position, format!("无法识别的字符 '{}'", c)));
+        }
+    }
+    Ok(tokens)
+}
+
+// 偏移最多一天，更长的偏移没有意义
+const MAX_OFFSET_HOURS: i64 = 24;
+
+// 解析 30m、1h30m、90s 这样的时长
+fn parse_duration(word: &str) -> Result<Duration, String> {
+    let too_long = || format!("时长 {} 太长，最多 {} 小时", word, MAX_OFFSET_HOURS);
+    let mut seconds: i64 = 0;
+    let mut rest = word;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(format!("无效的时长 {}", word));
+        }
+        let value: i64 = rest[..digits].parse().map_err(|_| too_long())?;
+        rest = &rest[digits..];
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit_seconds = match &rest[..unit] {
+            "h" => 3600,
+            "m" | "min" => 60,
+            "s" => 1,
+            "" => {
+                return Err(format!(
+                    "数字 {} 后面缺少单位(h、m 或 s)",
+                    &word[..word.len() - rest.len()]
+                ))
+            }
+            other => return Err(format!("未知的时间单位 {}，可用的有 h、m、s", other)),
+        };
+        seconds = value
+            .checked_mul(unit_seconds)
+            .and_then(|value| seconds.checked_add(value))
+            .filter(|&seconds| seconds <= MAX_OFFSET_HOURS * 3600)
+            .ok_or_else(too_long)?;
+        rest = &rest[unit..];
+    }
+    Ok(Duration::seconds(seconds))
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    // 文本末尾之后的位置，用于报告表达式不完整
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<(usize, Token)> {
+        self.tokens.get(self.next).cloned()
+    }
+
+    fn advance(&mut self) -> Option<(usize, Token)> {
+        let token = self.peek();
+        self.next += 1;
+        token
+    }
+
+    fn error_at(&self, position: usize, message: &str) -> ParseError {
+        ParseError {
+            position,
+            message: message.to_owned(),
+        }
+    }
+
+    // expr := primary (('+' | '-') 时长)*
+    fn expr(&mut self) -> Result<TimeExpr, ParseError> {
+        let mut expr = self.primary()?;
+        while let Some((_, sign @ (Token::Plus | Token::Minus))) = self.peek() {
+            self.advance();
+            let offset = match self.advance() {
+                Some((_, Token::Duration(duration))) => duration,
+                Some((position, token)) => {
+                    return Err(self.error_at(
+                        position,
+                        &format!("{} 后面应为时长，例如 30m，却遇到 {}", sign, token),
+                    ))
+                }
+                None => {
+                    return Err(self.error_at(
+                        self.end,
+                        &format!("表达式不完整，{} 后面应为时长，例如 30m", sign),
+                    ))
+                }
+            };
+            let offset = if sign == Token::Minus {
+                -offset
+            } else {
+                offset
+            };
+            expr = TimeExpr::Offset(Box::new(expr), offset);
+        }
+        Ok(expr)
+    }
+
+    // primary := 时间 | 太阳事件 | min(expr, ...) | max(expr, ...) | (expr)
+    fn primary(&mut self) -> Result<TimeExpr, ParseError> {
+        match self.advance() {
+            Some((_, Token::Time(time))) => Ok(TimeExpr::Time(time)),
+            Some((_, Token::Open)) => {
+                let expr = self.expr()?;
+                self.expect_close()?;
+                Ok(expr)
+            }
+            Some((position, Token::Name(name))) => {
+                if let Some(event) = SunEvent::ALL.into_iter().find(|e| e.name() == name) {
+                    return Ok(TimeExpr::Sun(event));
+                }
+                let function = match name.as_str() {
+                    "min" => TimeExpr::Min,
+                    "max" => TimeExpr::Max,
+                    _ => {
+                        return Err(self.error_at(
+                            position,
+                            &format!(
+                                "未知的名称 {}，可用的有 sunrise、sunset、dawn、dusk、min、max",
+                                name
+                            ),
+                        ))
+                    }
+                };
+                match self.advance() {
+                    Some((_, Token::Open)) => {}
+                    _ => {
+                        return Err(self.error_at(position, &format!("{} 后面应为 '('", name)));
+                    }
+                }
+                let mut args = vec![self.expr()?];
+                while let Some((_, Token::Comma)) = self.peek() {
+                    self.advance();
+                    args.push(self.expr()?);
+                }
+                self.expect_close()?;
+                Ok(function(args))
+            }
+            Some((position, token)) => Err(self.error_at(
+                position,
+                &format!("应为时间、太阳事件或 min/max，却遇到 {}", token),
+            )),
+            None => Err(self.error_at(self.end, "表达式不完整，缺少时间")),
+        }
+    }
+
+    fn expect_close(&mut self) -> Result<(), ParseError> {
+        match self.advance() {
+            Some((_, Token::Close)) => Ok(()),
+            Some((position, token)) => {
+                Err(self.error_at(position, &format!("应为 ')'，却遇到 {}", token)))
+            }
+            None => Err(self.error_at(self.end, "表达式不完整，缺少 ')'")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::FixedOffset;
+
+    fn hm(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn parse(text: &str) -> TimeExpr {
+        text.parse().unwrap_or_else(|e| panic!("{:?}: {}", text, e))
+    }
+
+    fn error(text: &str) -> String {
+        text.parse::<TimeExpr>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parses_and_prints_expressions() {
+        assert_eq!(parse("22:00"), TimeExpr::Time(hm(22, 0)));
+        assert_eq!(parse("07:30:15").to_string(), "07:30:15");
+        assert_eq!(
+            parse("max(sunset - 30m, 18:00)"),
+            TimeExpr::Max(vec![
+                TimeExpr::Offset(
+                    Box::new(TimeExpr::Sun(SunEvent::Sunset)),
+                    -Duration::minutes(30)
+                ),
+                TimeExpr::Time(hm(18, 0)),
+            ])
+        );
+        // 输出统一格式，再次解析得到相同的结果
+        for (text, printed) in [
+            ("max(sunset - 30m, 18:00)", "max(sunset - 30m, 18:00:00)"),
+            ("MIN( Sunrise,07:30 )", "min(sunrise, 07:30:00)"),
+            ("(dusk + 1h30m) - 90s", "dusk + 1h30m - 1m30s"),
+            (
+                "min(max(dawn, 05:00), sunrise + 0m)",
+                "min(max(dawn, 05:00:00), sunrise + 0m)",
+            ),
+        ] {
+            let expr = parse(text);
+            assert_eq!(expr.to_string(), printed);
+            assert_eq!(parse(printed), expr);
+        }
+    }
+
+    #[test]
+    fn reports_where_parsing_failed() {
+        assert_eq!(error(""), "第 1 个字符：表达式为空");
+        assert_eq!(
+            error("max(sunset - 30, 18:00)"),
+            "第 14 个字符：数字 30 后面缺少单位(h、m 或 s)"
+        );
+        assert_eq!(
+            error("sunset - 18:00"),
+            "第 10 个字符：'-' 后面应为时长，例如 30m，却遇到 18:00:00"
+        );
+        assert_eq!(
+            error("min(sunrise, 07:30"),
+            "第 19 个字符：表达式不完整，缺少 ')'"
+        );
+        assert_eq!(
+            error("moonrise"),
+            "第 1 个字符：未知的名称 moonrise，可用的有 sunrise、sunset、dawn、dusk、min、max"
+        );
+        assert_eq!(
+            error("25:00"),
+            "第 1 个字符：无效的时间 25:00，应为 HH:MM 或 HH:MM:SS"
+        );
+        assert_eq!(error("sunset 18:00"), "第 8 个字符：多余的内容 18:00:00");
+        assert_eq!(error("日落"), "第 1 个字符：无法识别的字符 '日'");
+        assert_eq!(
+            error("sunset + 5d"),
+            "第 10 个字符：未知的时间单位 d，可用的有 h、m、s"
+        );
+        assert_eq!(
+            error("sunset + 3000000000000h"),
+            "第 10 个字符：时长 3000000000000h 太长，最多 24 小时"
+        );
+        assert_eq!(
+            error("sunset - 23h61m"),
+            "第 10 个字符：时长 23h61m 太长，最多 24 小时"
+        );
+        assert_eq!(
+            error("sunset + 99999999999999999999s"),
+            "第 10 个字符：时长 99999999999999999999s 太长，最多 24 小时"
+        );
+        assert_eq!(parse("sunset + 24h").to_string(), "sunset + 24h");
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Wrapper {
+            start: TimeExpr,
+        }
+        let wrapper = Wrapper {
+            start: parse("max(sunset - 30m, 18:00)"),
+        };
+        let text = toml::to_string(&wrapper).unwrap();
+        assert_eq!(text.trim(), "start = \"max(sunset - 30m, 18:00:00)\"");
+        assert_eq!(toml::from_str::<Wrapper>(&text).unwrap(), wrapper);
+        // 以前保存的固定时间仍然可以读取
+        assert_eq!(
+            toml::from_str::<Wrapper>("start = \"22:00:00\"")
+                .unwrap()
+                .start,
+            TimeExpr::Time(hm(22, 0))
+        );
+        let error = toml::from_str::<Wrapper>("start = \"sunset -\"").unwrap_err();
+        assert!(error.to_string().contains("表达式不完整"), "{}", error);
+    }
+
+    #[test]
+    fn evaluates_per_day() {
+        let london = Location {
+            latitude: 51.5074,
+            longitude: -0.1278,
+        };
+        let utc = FixedOffset::east_opt(0).unwrap();
+        let winter = NaiveDate::from_ymd_opt(2024, 12, 21).unwrap();
+        let summer = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let start = parse("max(sunset - 30m, 18:00)");
+        // 冬天日落约 UTC 15:53，取 18:00；夏天日落约 UTC 20:21，取 19:51 左右
+        assert_eq!(start.eval(winter, london, &utc), Some(hm(18, 0)));
+        let evening = start.eval(summer, london, &utc).unwrap();
+        assert!(
+            evening > hm(19, 45) && evening < hm(19, 57) && evening.nanosecond() == 0,
+            "{}",
+            evening
+        );
+        let end = parse("min(sunrise, 07:30)");
+        assert_eq!(end.eval(winter, london, &utc), Some(hm(7, 30)));
+        assert!(end.eval(summer, london, &utc).unwrap() < hm(5, 0));
+        // 超出当天的结果限制在当天之内
+        assert_eq!(
+            parse("23:00 + 2h").eval(winter, london, &utc),
+            Some(NaiveTime::from_hms_opt(23, 59, 59).unwrap())
+        );
+        // 超出日期范围的偏移没有结果
+        let far = TimeExpr::Offset(Box::new(parse("23:00")), Duration::days(1_000_000_000));
+        assert_eq!(far.eval(winter, london, &utc), None);
+
+        // 极夜没有日落，只使用有值的参数
+        let tromso = Location {
+            latitude: 69.6492,
+            longitude: 18.9553,
+        };
+        assert_eq!(start.eval(winter, tromso, &utc), Some(hm(18, 0)));
+        assert_eq!(parse("sunset").eval(winter, tromso, &utc), None);
+        assert_eq!(parse("sunset").as_time(), None);
+        assert_eq!(parse("08:00").as_time(), Some(hm(8, 0)));
+    }
+}
@@ -0,0 +1,698 @@
+// 一天之内的时间段，例如深色模式的时间范围
+use crate::exceptions::{DateException, ExceptionKind};
+use crate::season::{self, DateRange};
+use crate::solar::Location;
+use crate::time_expr::TimeExpr;
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveTime, Weekday};
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeWindow {
+    // 不包含任何时间
+    Empty,
+    // 包含一整天
+    FullDay,
+    // 包含 start，不包含 end；start 晚于 end 时跨过午夜。start 不会等于 end
+    Range { start: NaiveTime, end: NaiveTime },
+}
+
+impl TimeWindow {
+    // 开始和结束相同的时间段长度为零
+    pub fn new(start: NaiveTime, end: NaiveTime) -> Self {
+        if start == end {
+            TimeWindow::Empty
+        } else {
+            TimeWindow::Range { start, end }
+        }
+    }
+
+    // 设置中输入的固定时间段，开始和结束相同表示全天，和旧版本的判断一致。
+    // 计算出来的时间段(日出日落、时间表达式)重合时仍然为空
+    pub fn from_user(start: NaiveTime, end: NaiveTime) -> Self {
+        if start == end {
+            TimeWindow::FullDay
+        } else {
+            TimeWindow::Range { start, end }
+        }
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        match *self {
+            TimeWindow::Empty => false,
+            TimeWindow::FullDay => true,
+            TimeWindow::Range { start, end } if start < end => start <= time && time < end,
+            TimeWindow::Range { start, end } => time >= start || time < end,
+        }
+    }
+
+    pub fn crosses_midnight(&self) -> bool {
+        matches!(*self, TimeWindow::Range { start, end } if start > end)
+    }
+
+    pub fn duration(&self) -> Duration {
+        match *self {
+            TimeWindow::Empty => Duration::zero(),
+            TimeWindow::FullDay => Duration::days(1),
+            TimeWindow::Range { start, end } if start < end => end - start,
+            TimeWindow::Range { start, end } => Duration::days(1) - (start - end),
+        }
+    }
+
+    // 时间段内外发生变化的时刻，不包括午夜
+    pub fn boundaries(&self) -> Vec<NaiveTime> {
+        match *self {
+            TimeWindow::Empty | TimeWindow::FullDay => Vec::new(),
+            TimeWindow::Range { start, end } => vec![start, end],
+        }
+    }
+
+    // 开始当天(午夜之前)的部分
+    pub fn same_day_part(&self) -> TimeWindow {
+        match *self {
+            TimeWindow::Range { start, .. } if self.crosses_midnight() => {
+                TimeWindow::new(start, NaiveTime::MIN)
+            }
+            window => window,
+        }
+    }
+
+    // 跨过午夜后属于第二天的部分
+    pub fn next_day_part(&self) -> TimeWindow {
+        match *self {
+            TimeWindow::Range { end, .. } if self.crosses_midnight() => {
+                TimeWindow::new(NaiveTime::MIN, end)
+            }
+            _ => TimeWindow::Empty,
+        }
+    }
+
+    // 两个时间段是否有共同的时间
+    pub fn overlaps(&self, other: &TimeWindow) -> bool {
+        self.segments().iter().any(|(start, end)| {
+            other
+                .segments()
+                .iter()
+                .any(|(other_start, other_end)| start < other_end && other_start < end)
+        })
+    }
+
+    // 拆成不跨午夜的区间，用距离午夜的时长 [start, end) 表示
+    fn segments(&self) -> Vec<(Duration, Duration)> {
+        let offset = |time: NaiveTime| time - NaiveTime::MIN;
+        match *self {
+            TimeWindow::Empty => Vec::new(),
+            TimeWindow::FullDay => vec![(Duration::zero(), Duration::days(1))],
+            TimeWindow::Range { start, end } if start < end => vec![(offset(start), offset(end))],
+            TimeWindow::Range { start, end } if end == NaiveTime::MIN => {
+                vec![(offset(start), Duration::days(1))]
+            }
+            TimeWindow::Range { start, end } => vec![
+                (Duration::zero(), offset(end)),
+                (offset(start), Duration::days(1)),
+            ],
+        }
+    }
+}
+
+// 多个时间段的并集，重叠或相连的时间段会被合并
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WindowSet(Vec<TimeWindow>);
+
+impl WindowSet {
+    pub fn new(windows: impl IntoIterator<Item = TimeWindow>) -> Self {
+        let mut segments: Vec<(Duration, Duration)> = windows
+            .into_iter()
+            .flat_map(|window| window.segments())
+            .collect();
+        segments.sort();
+        let mut merged: Vec<(Duration, Duration)> = Vec::new();
+        for (start, end) in segments {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        if merged.as_slice() == [(Duration::zero(), Duration::days(1))] {
+            return WindowSet(vec![TimeWindow::FullDay]);
+        }
+        // 从午夜开始和到午夜结束的两段其实是同一个跨午夜的时间段
+        if merged.len() > 1
+            && merged[0].0 == Duration::zero()
+            && merged[merged.len() - 1].1 == Duration::days(1)
+        {
+            let (_, end) = merged.remove(0);
+            merged.last_mut().unwrap().1 = end;
+        }
+        WindowSet(
+            merged
+                .into_iter()
+                // 加上一天会回到午夜
+                .map(|(start, end)| TimeWindow::new(NaiveTime::MIN + start, NaiveTime::MIN + end))
+                .collect(),
+        )
+    }
+
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        self.0.iter().any(|window| window.contains(time))
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.0
+            .iter()
+            .fold(Duration::zero(), |total, window| total + window.duration())
+    }
+
+    pub fn boundaries(&self) -> Vec<NaiveTime> {
+        self.0
+            .iter()
+            .flat_map(|window| window.boundaries())
+            .collect()
+    }
+}
+
+impl From<TimeWindow> for WindowSet {
+    fn from(window: TimeWindow) -> Self {
+        WindowSet::new([window])
+    }
+}
+
+// 设置中的一个时间段，开始或结束用到太阳事件时每天的时间不同
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowRule {
+    Fixed(TimeWindow),
+    Expr { start: TimeExpr, end: TimeExpr },
+}
+
+impl WindowRule {
+    pub fn new(start: TimeExpr, end: TimeExpr) -> Self {
+        match (start.as_time(), end.as_time()) {
+            (Some(start), Some(end)) => WindowRule::Fixed(TimeWindow::from_user(start, end)),
+            _ => WindowRule::Expr { start, end },
+        }
+    }
+
+    // 某天在本地时区的时间段，开始或结束没有值(极昼、极夜)时为空
+    pub fn on(&self, date: NaiveDate, location: Location) -> TimeWindow {
+        match self {
+            WindowRule::Fixed(window) => *window,
+            WindowRule::Expr { start, end } => match (
+                start.eval(date, location, &Local),
+                end.eval(date, location, &Local),
+            ) {
+                (Some(start), Some(end)) => TimeWindow::new(start, end),
+                _ => TimeWindow::Empty,
+            },
+        }
+    }
+
+    pub fn as_fixed(&self) -> Option<TimeWindow> {
+        match self {
+            WindowRule::Fixed(window) => Some(*window),
+            WindowRule::Expr { .. } => None,
+        }
+    }
+}
+
+impl From<TimeWindow> for WindowRule {
+    fn from(window: TimeWindow) -> Self {
+        WindowRule::Fixed(window)
+    }
+}
+
+impl fmt::Display for WindowRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WindowRule::Fixed(window) => write!(f, "{}", window),
+            WindowRule::Expr { start, end } => write!(f, "{} 至 {}", start, end),
+        }
+    }
+}
+
+// 一周七天各自的时间段，跨午夜的时间段属于开始的那一天，
+// 例如周五 23:00 到周六 08:00 的时间段按周五的设置计算。
+// 特殊日期优先于平常的设置：全天浅色或深色的日期不受前一天影响，
+// 使用备用时间段的日期和平常一样处理跨午夜的部分。
+// 设置了季节时，属于某个季节的日期使用该季节的时间段，不再区分星期几。
+// 时间表达式按 location 计算日出日落
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WeekWindows {
+    days: [Vec<WindowRule>; 7],
+    alternate: Vec<WindowRule>,
+    seasons: Vec<(DateRange, Vec<WindowRule>)>,
+    exceptions: BTreeMap<NaiveDate, ExceptionKind>,
+    location: Location,
+}
+
+impl WeekWindows {
+    pub fn every_day(windows: impl IntoIterator<Item = impl Into<WindowRule>>) -> Self {
+        let windows: Vec<WindowRule> = windows.into_iter().map(Into::into).collect();
+        WeekWindows {
+            days: std::array::from_fn(|_| windows.clone()),
+            ..Default::default()
+        }
+    }
+
+    pub fn set(
+        &mut self,
+        weekday: Weekday,
+        windows: impl IntoIterator<Item = impl Into<WindowRule>>,
+    ) {
+        self.days[weekday.num_days_from_monday() as usize] =
+            windows.into_iter().map(Into::into).collect();
+    }
+
+    pub fn set_alternate(&mut self, windows: impl IntoIterator<Item = impl Into<WindowRule>>) {
+        self.alternate = windows.into_iter().map(Into::into).collect();
+    }
+
+    pub fn set_seasons(&mut self, seasons: impl IntoIterator<Item = (DateRange, Vec<WindowRule>)>) {
+        self.seasons = seasons.into_iter().collect();
+    }
+
+    pub fn set_location(&mut self, location: Location) {
+        self.location = location;
+    }
+
+    pub fn set_exceptions<'a>(&mut self, exceptions: impl IntoIterator<Item = &'a DateException>) {
+        self.exceptions = exceptions
+            .into_iter()
+            .map(|exception| (exception.date, exception.kind))
+            .collect();
+    }
+
+    pub fn exception(&self, date: NaiveDate) -> Option<ExceptionKind> {
+        self.exceptions.get(&date).copied()
+    }
+
+    // 某天开始的时间段，包括跨到第二天的部分
+    fn starting_on(&self, date: NaiveDate) -> Vec<TimeWindow> {
+        let ranges: Vec<DateRange> = self.seasons.iter().map(|(range, _)| *range).collect();
+        let rules = match self.exception(date) {
+            None => season::position(&ranges, date)
+                .map(|i| self.seasons[i].1.as_slice())
+                .unwrap_or(&self.days[date.weekday().num_days_from_monday() as usize]),
+            Some(ExceptionKind::Alternate) => &self.alternate,
+            Some(ExceptionKind::AlwaysLight) => return Vec::new(),
+            Some(ExceptionKind::AlwaysDark) => return vec![TimeWindow::FullDay],
+        };
+        rules
+            .iter()
+            .map(|rule| rule.on(date, self.location))
+            .collect()
+    }
+
+    // 某个日期当天 0 点到 24 点之间的时间段，包括前一天跨过午夜的部分
+    pub fn on(&self, date: NaiveDate) -> WindowSet {
+        match self.exception(date) {
+            Some(ExceptionKind::AlwaysLight) => return WindowSet::default(),
+            Some(ExceptionKind::AlwaysDark) => return TimeWindow::FullDay.into(),
+            _ => {}
+        }
+        let today = self
+            .starting_on(date)
+            .into_iter()
+            .map(|window| window.same_day_part());
+        let yesterday = self
+            .starting_on(date - Duration::days(1))
+            .into_iter()
+            .map(|window| window.next_day_part());
+        WindowSet::new(today.chain(yesterday))
+    }
+}
+
+// 互相重叠的时间段的下标
+pub fn overlapping_pairs(windows: &[TimeWindow]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    for (i, a) in windows.iter().enumerate() {
+        for (j, b) in windows.iter().enumerate().skip(i + 1) {
+            if a.overlaps(b) {
+                pairs.push((i, j));
+            }
+        }
+    }
+    pairs
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TimeWindow::Empty => write!(f, "无"),
+            TimeWindow::FullDay => write!(f, "全天"),
+            TimeWindow::Range { start, end } => write!(
+                f,
+                "{} - {}{}",
+                start.format("%H:%M:%S"),
+                if self.crosses_midnight() {
+                    "次日"
+                } else {
+                    ""
+                },
+                end.format("%H:%M:%S")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for WindowSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "无");
+        }
+        for (i, window) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "、")?;
+            }
+            write!(f, "{}", window)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hm(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    // 每分钟检查一次，返回包含在时间段内的分钟数
+    fn minutes_inside(window: TimeWindow) -> i64 {
+        (0..24 * 60)
+            .filter(|minute| window.contains(hm(minute / 60, minute % 60)))
+            .count() as i64
+    }
+
+    #[test]
+    fn overnight_window() {
+        let window = TimeWindow::new(hm(18, 0), hm(8, 0));
+        assert!(window.crosses_midnight());
+        assert!(window.contains(hm(18, 0)));
+        assert!(window.contains(hm(23, 59)));
+        assert!(window.contains(NaiveTime::MIN));
+        assert!(window.contains(hm(7, 59)));
+        assert!(!window.contains(hm(8, 0)));
+        assert!(!window.contains(hm(17, 59)));
+        assert_eq!(window.duration(), Duration::hours(14));
+    }
+
+    #[test]
+    fn daytime_window() {
+        // 夜班用户白天深色
+        let window = TimeWindow::new(hm(9, 0), hm(17, 0));
+        assert!(!window.crosses_midnight());
+        assert!(window.contains(hm(9, 0)));
+        assert!(window.contains(hm(16, 59)));
+        assert!(!window.contains(hm(17, 0)));
+        assert!(!window.contains(hm(8, 59)));
+        assert!(!window.contains(NaiveTime::MIN));
+        assert!(!window.contains(hm(23, 59)));
+        assert_eq!(window.duration(), Duration::hours(8));
+    }
+
+    #[test]
+    fn equal_start_and_end_is_empty() {
+        let window = TimeWindow::new(hm(12, 0), hm(12, 0));
+        assert_eq!(window, TimeWindow::Empty);
+        assert!(!window.contains(hm(12, 0)));
+        assert_eq!(minutes_inside(window), 0);
+        assert_eq!(window.duration(), Duration::zero());
+        assert!(window.boundaries().is_empty());
+    }
+
+    #[test]
+    fn equal_user_times_are_the_full_day() {
+        let rule = WindowRule::new(hm(12, 0).into(), hm(12, 0).into());
+        assert_eq!(rule.as_fixed(), Some(TimeWindow::FullDay));
+        assert_eq!(
+            TimeWindow::from_user(NaiveTime::MIN, NaiveTime::MIN),
+            TimeWindow::FullDay
+        );
+        assert_eq!(
+            TimeWindow::from_user(hm(22, 0), hm(7, 0)),
+            TimeWindow::new(hm(22, 0), hm(7, 0))
+        );
+        // 计算出来的时间重合时没有深色时间
+        let date = NaiveDate::from_ymd_opt(2024, 1, 15).unwrap();
+        let collapsed =
+            WindowRule::new("23:00 + 2h".parse().unwrap(), "23:30 + 1h".parse().unwrap());
+        assert_eq!(collapsed.on(date, Location::default()), TimeWindow::Empty);
+    }
+
+    #[test]
+    fn full_day_contains_everything() {
+        let window = TimeWindow::FullDay;
+        assert!(window.contains(NaiveTime::MIN));
+        assert!(window.contains(NaiveTime::from_hms_opt(23, 59, 59).unwrap()));
+        assert_eq!(minutes_inside(window), 24 * 60);
+        assert_eq!(window.duration(), Duration::days(1));
+        assert!(!window.crosses_midnight());
+    }
+
+    #[test]
+    fn windows_touching_midnight() {
+        let until_midnight = TimeWindow::new(hm(20, 0), NaiveTime::MIN);
+        assert!(until_midnight.contains(hm(23, 59)));
+        assert!(!until_midnight.contains(NaiveTime::MIN));
+        assert_eq!(until_midnight.duration(), Duration::hours(4));
+        let from_midnight = TimeWindow::new(NaiveTime::MIN, hm(6, 0));
+        assert!(from_midnight.contains(NaiveTime::MIN));
+        assert!(!from_midnight.contains(hm(6, 0)));
+        assert!(!from_midnight.crosses_midnight());
+    }
+
+    #[test]
+    fn duration_matches_contained_minutes_for_every_hour_pair() {
+        for start in 0..24 {
+            for end in 0..24 {
+                let window = TimeWindow::new(hm(start, 0), hm(end, 0));
+                assert_eq!(
+                    window.duration().num_minutes(),
+                    minutes_inside(window),
+                    "{}",
+                    window
+                );
+                // 时间段外的时间正好是反方向的时间段
+                let inverse = TimeWindow::new(hm(end, 0), hm(start, 0));
+                if start != end {
+                    for minute in (0..24 * 60).step_by(15) {
+                        let time = hm(minute / 60, minute % 60);
+                        assert_ne!(window.contains(time), inverse.contains(time), "{}", time);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            TimeWindow::new(hm(18, 0), hm(8, 30)).to_string(),
+            "18:00:00 - 次日08:30:00"
+        );
+        assert_eq!(
+            TimeWindow::new(hm(9, 0), hm(17, 0)).to_string(),
+            "09:00:00 - 17:00:00"
+        );
+        assert_eq!(TimeWindow::Empty.to_string(), "无");
+        assert_eq!(TimeWindow::FullDay.to_string(), "全天");
+    }
+
+    fn set(windows: &[(u32, u32)]) -> WindowSet {
+        WindowSet::new(
+            windows
+                .iter()
+                .map(|&(start, end)| TimeWindow::new(hm(start, 0), hm(end, 0))),
+        )
+    }
+
+    #[test]
+    fn separate_windows_are_kept_in_order() {
+        // 午休和晚上
+        let windows = set(&[(22, 7), (12, 13)]);
+        assert_eq!(
+            windows.0,
+            [
+                TimeWindow::new(hm(12, 0), hm(13, 0)),
+                TimeWindow::new(hm(22, 0), hm(7, 0)),
+            ]
+        );
+        assert!(windows.contains(hm(12, 30)));
+        assert!(windows.contains(hm(3, 0)));
+        assert!(!windows.contains(hm(10, 0)));
+        assert_eq!(windows.duration(), Duration::hours(10));
+        assert_eq!(
+            windows.to_string(),
+            "12:00:00 - 13:00:00、22:00:00 - 次日07:00:00"
+        );
+    }
+
+    #[test]
+    fn overlapping_and_touching_windows_are_merged() {
+        assert_eq!(set(&[(18, 22), (20, 23)]), set(&[(18, 23)]));
+        assert_eq!(set(&[(18, 20), (20, 23)]), set(&[(18, 23)]));
+        // 跨午夜的时间段与清晨的时间段合并
+        assert_eq!(set(&[(22, 2), (1, 7)]), set(&[(22, 7)]));
+        assert_eq!(set(&[(20, 0), (0, 6)]), set(&[(20, 6)]));
+        assert_eq!(set(&[(9, 17), (17, 9)]), TimeWindow::FullDay.into());
+        assert_eq!(set(&[(10, 11), (10, 11)]), set(&[(10, 11)]));
+        assert_eq!(set(&[(12, 12)]), WindowSet::default());
+        assert_eq!(WindowSet::default().to_string(), "无");
+    }
+
+    #[test]
+    fn merged_set_contains_the_union() {
+        let inputs = [(22, 2), (1, 7), (12, 13), (12, 14), (6, 6), (23, 0)];
+        let windows: Vec<TimeWindow> = inputs
+            .iter()
+            .map(|&(start, end)| TimeWindow::new(hm(start, 0), hm(end, 0)))
+            .collect();
+        let merged = WindowSet::new(windows.clone());
+        for minute in 0..24 * 60 {
+            let time = hm(minute / 60, minute % 60);
+            assert_eq!(
+                merged.contains(time),
+                windows.iter().any(|window| window.contains(time)),
+                "{}",
+                time
+            );
+        }
+        assert_eq!(overlapping_pairs(&windows), [(0, 1), (0, 5), (2, 3)]);
+    }
+
+    #[test]
+    fn splitting_at_midnight() {
+        let night = TimeWindow::new(hm(23, 0), hm(8, 0));
+        assert_eq!(
+            night.same_day_part(),
+            TimeWindow::new(hm(23, 0), NaiveTime::MIN)
+        );
+        assert_eq!(
+            night.next_day_part(),
+            TimeWindow::new(NaiveTime::MIN, hm(8, 0))
+        );
+        let day = TimeWindow::new(hm(9, 0), hm(17, 0));
+        assert_eq!(day.same_day_part(), day);
+        assert_eq!(day.next_day_part(), TimeWindow::Empty);
+        let until_midnight = TimeWindow::new(hm(20, 0), NaiveTime::MIN);
+        assert_eq!(until_midnight.same_day_part(), until_midnight);
+        assert_eq!(until_midnight.next_day_part(), TimeWindow::Empty);
+        assert_eq!(TimeWindow::FullDay.same_day_part(), TimeWindow::FullDay);
+        assert_eq!(TimeWindow::FullDay.next_day_part(), TimeWindow::Empty);
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        // 2024-01-01 是周一
+        NaiveDate::from_ymd_opt(2024, 1, day).unwrap()
+    }
+
+    #[test]
+    fn friday_night_ends_on_saturday_morning() {
+        let mut week = WeekWindows::every_day([TimeWindow::new(hm(22, 0), hm(7, 0))]);
+        week.set(Weekday::Fri, [TimeWindow::new(hm(23, 0), hm(10, 0))]);
+        week.set(Weekday::Sat, [TimeWindow::new(hm(1, 0), hm(9, 0))]);
+        week.set(Weekday::Sun, Vec::<TimeWindow>::new());
+
+        let friday = week.on(date(5));
+        // 周四晚上的时间段在周五 7 点结束
+        assert!(friday.contains(hm(6, 59)));
+        assert!(!friday.contains(hm(7, 0)));
+        assert!(!friday.contains(hm(22, 30)));
+        assert!(friday.contains(hm(23, 0)));
+
+        let saturday = week.on(date(6));
+        // 周五的时间段延续到周六 10 点，和周六自己的时间段合并
+        assert!(saturday.contains(NaiveTime::MIN));
+        assert!(saturday.contains(hm(9, 30)));
+        assert!(!saturday.contains(hm(10, 0)));
+        assert!(!saturday.contains(hm(23, 0)));
+
+        let sunday = week.on(date(7));
+        assert_eq!(sunday, WindowSet::default());
+        // 周日没有时间段，周一凌晨也不是深色，周一晚上照常
+        let monday = week.on(date(8));
+        assert!(!monday.contains(hm(3, 0)));
+        assert!(monday.contains(hm(22, 0)));
+    }
+
+    #[test]
+    fn every_day_matches_the_plain_window_set() {
+        let windows = [
+            TimeWindow::new(hm(22, 0), hm(7, 0)),
+            TimeWindow::new(hm(12, 0), hm(13, 0)),
+        ];
+        let week = WeekWindows::every_day(windows);
+        for day in 1..=7 {
+            assert_eq!(week.on(date(day)), WindowSet::new(windows));
+        }
+    }
+
+    #[test]
+    fn exceptions_take_precedence_over_regular_days() {
+        let mut week = WeekWindows::every_day([TimeWindow::new(hm(22, 0), hm(7, 0))]);
+        week.set_alternate([TimeWindow::new(hm(1, 0), hm(11, 0))]);
+        let exception = |day, kind| DateException {
+            date: date(day),
+            kind,
+            summary: String::new(),
+        };
+        week.set_exceptions(&[
+            exception(2, ExceptionKind::AlwaysLight),
+            exception(4, ExceptionKind::AlwaysDark),
+            exception(6, ExceptionKind::Alternate),
+        ]);
+        // 全天浅色，前一天晚上的时间段也不延续过来
+        assert_eq!(week.on(date(2)), WindowSet::default());
+        // 第二天凌晨不受影响，照常晚上开始
+        assert!(!week.on(date(3)).contains(hm(3, 0)));
+        assert!(week.on(date(3)).contains(hm(22, 0)));
+        assert_eq!(week.on(date(4)), TimeWindow::FullDay.into());
+        assert!(!week.on(date(5)).contains(hm(3, 0)));
+        // 备用时间段的日期仍然包含前一天晚上延续过来的部分
+        let alternate = week.on(date(6));
+        assert!(alternate.contains(hm(0, 30)));
+        assert!(alternate.contains(hm(10, 0)));
+        assert!(!alternate.contains(hm(22, 0)));
+        assert_eq!(week.exception(date(6)), Some(ExceptionKind::Alternate));
+        assert_eq!(week.exception(date(7)), None);
+    }
+
+    #[test]
+    fn seasons_replace_weekday_windows() {
+        use crate::season::MonthDay;
+        let mut week = WeekWindows::every_day([TimeWindow::new(hm(22, 0), hm(7, 0))]);
+        let range = |from: (u32, u32), to: (u32, u32)| DateRange {
+            from: MonthDay::new(from.0, from.1).unwrap(),
+            to: MonthDay::new(to.0, to.1).unwrap(),
+        };
+        week.set_seasons([
+            (
+                range((4, 1), (9, 30)),
+                vec![TimeWindow::new(hm(21, 0), hm(6, 0)).into()],
+            ),
+            (
+                range((10, 1), (12, 31)),
+                vec![TimeWindow::new(hm(17, 30), hm(7, 30)).into()],
+            ),
+        ]);
+        let day = |month, day| NaiveDate::from_ymd_opt(2024, month, day).unwrap();
+        assert!(week.on(day(6, 1)).contains(hm(21, 0)));
+        assert!(!week.on(day(6, 1)).contains(hm(6, 30)));
+        // 季节交替的那天凌晨仍是前一季节晚上延续过来的部分
+        let first_of_winter = week.on(day(10, 1));
+        assert!(!first_of_winter.contains(hm(6, 30)));
+        assert!(first_of_winter.contains(hm(17, 30)));
+        assert!(week.on(day(10, 2)).contains(hm(7, 0)));
+        // 不属于任何季节的日期使用平常的设置
+        assert!(!week.on(day(2, 1)).contains(hm(21, 0)));
+        assert!(week.on(day(2, 1)).contains(hm(22, 0)));
+        // 特殊日期仍然优先
+        week.set_exceptions(&[DateException {
+            date: day(6, 1),
+            kind: ExceptionKind::AlwaysLight,
+            summary: String::new(),
+        }]);
+        assert_eq!(week.on(day(6, 1)), WindowSet::default());
+    }
+}
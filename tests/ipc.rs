@@ -0,0 +1,304 @@
+// 启动真正的后台实例，通过命令行子命令和控制接口交互
+use std::path::PathBuf;
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+const BINARY: &str = env!("CARGO_BIN_EXE_autodark-egui-rr-test");
+
+const CONFIG: &str = "is_dark_mode = false
+is_system_dark_mode = false
+is_system_both_dark_mode = true
+auto_mode_change = false
+auto_system_mode_change = false
+is_autostart = false
+location_name = \"测试\"
+";
+
+struct Instance {
+    dir: tempfile::TempDir,
+    socket: PathBuf,
+}
+
+impl Instance {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("config.toml"), CONFIG).unwrap();
+        let socket = dir.path().join("autodark.sock");
+        Self { dir, socket }
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(BINARY);
+        command
+            .arg("--config")
+            .arg(self.dir.path().join("config.toml"))
+            .args(args)
+            .current_dir(self.dir.path())
+            .env("AUTODARK_BACKEND", "memory")
+            .env("AUTODARK_SOCKET", &self.socket);
+        command
+    }
+
+    fn start(&self) -> Daemon {
+        let daemon = Daemon(
+            self.command(&["--daemon"])
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap(),
+        );
+        // 等到开始监听
+        for _ in 0..100 {
+            if self.listening() {
+                return daemon;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("daemon did not start listening");
+    }
+
+    #[cfg(unix)]
+    fn listening(&self) -> bool {
+        std::os::unix::net::UnixStream::connect(&self.socket).is_ok()
+    }
+
+    #[cfg(windows)]
+    fn listening(&self) -> bool {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.socket)
+            .is_ok()
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().unwrap()
+    }
+
+    fn status(&self) -> serde_json::Value {
+        let output = self.run(&["status", "--json"]);
+        assert!(output.status.success(), "{:?}", output);
+        serde_json::from_slice(&output.stdout).unwrap()
+    }
+}
+
+struct Daemon(Child);
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn commands_are_sent_to_the_running_instance() {
+    let instance = Instance::new();
+    let _daemon = instance.start();
+    let status = instance.status();
+    assert_eq!(status["backend"], "memory");
+    assert_eq!(status["app_dark"], false);
+    assert_eq!(status["paused"], false);
+
+    // 内存后端只在后台实例里，能读到修改说明命令是由它执行的
+    assert!(instance.run(&["set", "dark"]).status.success());
+    let status = instance.status();
+    assert_eq!(status["app_dark"], true);
+    assert_eq!(status["system_dark"], true);
+
+    let output = instance.run(&["toggle", "--target", "app"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "浅色模式\n");
+    assert_eq!(instance.status()["app_dark"], false);
+
+    let output = instance.run(&["pause"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "已暂停自动切换\n");
+    assert_eq!(instance.status()["paused"], true);
+    assert!(instance.run(&["resume"]).status.success());
+    assert_eq!(instance.status()["paused"], false);
+
+    // 修改配置后重新读取，开启全天深色的自动切换
+    let config = format!(
+        "{}{}",
+        CONFIG.replace("auto_mode_change = false", "auto_mode_change = true"),
+        "\n[[night_windows]]\nstart = \"00:00:00\"\nend = \"12:00:00\"\n\n[[night_windows]]\nstart = \"12:00:00\"\nend = \"00:00:00\"\n"
+    );
+    std::fs::write(instance.dir.path().join("config.toml"), config).unwrap();
+    let output = instance.run(&["reload"]);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "已重新读取配置\n");
+    let mut status = instance.status();
+    for _ in 0..50 {
+        if status["app_dark"] == true {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+        status = instance.status();
+    }
+    assert_eq!(status["auto_app"], true);
+    assert_eq!(status["app_dark"], true);
+
+    // 配置文件有错误时报告给客户端，保留原来的设置
+    std::fs::write(instance.dir.path().join("config.toml"), "is_dark_mode = ").unwrap();
+    let output = instance.run(&["reload"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(!output.stderr.is_empty());
+    assert_eq!(instance.status()["auto_app"], true);
+}
+
+#[test]
+fn commands_fall_back_to_direct_mode_without_an_instance() {
+    let instance = Instance::new();
+    let status = instance.status();
+    assert_eq!(status["backend"], "memory");
+    assert_eq!(status["paused"], false);
+
+    let output = instance.run(&["pause"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).trim_end(),
+        "没有正在运行的实例"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn stale_socket_is_replaced() {
+    let instance = Instance::new();
+    // 被强制结束的实例留下的套接字文件
+    drop(instance.start());
+    assert!(instance.socket.exists());
+    let _daemon = instance.start();
+    assert!(instance.run(&["set", "dark"]).status.success());
+    assert_eq!(instance.status()["app_dark"], true);
+}
+
+#[test]
+fn second_launch_hands_off_to_the_running_instance() {
+    let instance = Instance::new();
+    let _daemon = instance.start();
+    let output = instance.run(&["--daemon"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).trim_end(),
+        "已经有实例在运行"
+    );
+
+    // 打开窗口时请求已经在运行的实例显示窗口，后台模式没有窗口可以显示
+    let output = instance.run(&[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).trim_end(),
+        "已经有实例在后台模式运行，没有可以显示的窗口"
+    );
+    // 第一个实例不受影响
+    assert_eq!(instance.status()["backend"], "memory");
+}
+
+#[cfg(unix)]
+#[test]
+fn second_launch_gives_up_on_a_stuck_instance() {
+    let instance = Instance::new();
+    // 接受连接但从不回复的实例
+    let _listener = std::os::unix::net::UnixListener::bind(&instance.socket).unwrap();
+    let started = std::time::Instant::now();
+    let output = instance.run(&[]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).trim_end(),
+        "正在运行的实例没有响应"
+    );
+    assert!(started.elapsed() < Duration::from_secs(30));
+}
+
+// 不带 --config，使用 dir/user 作为用户的配置目录，在 dir/work 中运行
+fn run_in_work_dir(dir: &std::path::Path, args: &[&str]) -> Command {
+    let user_dir = dir.join("user");
+    let mut command = Command::new(BINARY);
+    command
+        .args(args)
+        .current_dir(dir.join("work"))
+        .env("AUTODARK_BACKEND", "memory")
+        .env("AUTODARK_SOCKET", dir.join("autodark.sock"))
+        .env("XDG_CONFIG_HOME", &user_dir)
+        .env("APPDATA", &user_dir);
+    command
+}
+
+#[test]
+fn config_in_the_working_directory_is_copied_to_the_user_config_dir() {
+    let dir = tempfile::tempdir().unwrap();
+    let work_dir = dir.path().join("work");
+    let user_config = dir.path().join("user").join("autodark").join("config.toml");
+    std::fs::create_dir(&work_dir).unwrap();
+    let config = CONFIG.replace("auto_mode_change = false", "auto_mode_change = true");
+    std::fs::write(work_dir.join("config.toml"), &config).unwrap();
+
+    // 子命令不复制
+    let output = run_in_work_dir(dir.path(), &["status", "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert!(!user_config.exists());
+
+    let daemon = Daemon(
+        run_in_work_dir(dir.path(), &["--daemon"])
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let mut status = run_in_work_dir(dir.path(), &["status", "--json"])
+        .output()
+        .unwrap();
+    for _ in 0..100 {
+        if serde_json::from_slice::<serde_json::Value>(&status.stdout).unwrap()["auto_app"] == true
+        {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+        status = run_in_work_dir(dir.path(), &["status", "--json"])
+            .output()
+            .unwrap();
+    }
+    drop(daemon);
+    let status: serde_json::Value = serde_json::from_slice(&status.stdout).unwrap();
+    assert_eq!(status["auto_app"], true);
+    assert!(user_config.exists());
+    // 原来的文件保留
+    assert_eq!(
+        std::fs::read_to_string(work_dir.join("config.toml")).unwrap(),
+        config
+    );
+}
+
+#[test]
+fn other_config_files_in_the_working_directory_are_left_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let work_dir = dir.path().join("work");
+    std::fs::create_dir(&work_dir).unwrap();
+    let hugo =
+        "baseURL = \"https://example.org/\"\ntitle = \"博客\"\n\n[params]\ntheme = \"dark\"\n";
+    std::fs::write(work_dir.join("config.toml"), hugo).unwrap();
+    let _daemon = Daemon(
+        run_in_work_dir(dir.path(), &["--daemon"])
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    // 等后台实例开始运行
+    let mut output = run_in_work_dir(dir.path(), &["pause"]).output().unwrap();
+    for _ in 0..100 {
+        if output.status.success() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+        output = run_in_work_dir(dir.path(), &["pause"]).output().unwrap();
+    }
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(
+        std::fs::read_to_string(work_dir.join("config.toml")).unwrap(),
+        hugo
+    );
+    let user_config = dir.path().join("user").join("autodark").join("config.toml");
+    assert!(!std::fs::read_to_string(user_config)
+        .unwrap_or_default()
+        .contains("baseURL"));
+}